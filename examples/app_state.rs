@@ -3,16 +3,16 @@ use std::env;
 use std::error::Error;
 use std::path::PathBuf;
 use std::path::Path;
-use std::sync::{Arc, RwLock};
+use std::sync::RwLock;
 
 // For parsing command line:
 use clap::{Command, Args, FromArgMatches as _};
 // Logging:
 use log::{error, info};
-use env_logger;
 
 // Our HTTP server:
-use shttp::{ServerConfig, http};
+use shttp::{ServerConfig, Router, Middleware, Request, Response, Status};
+use shttp::http::res::Content::*;
 
 /// Path for server html files, relative to the executable
 const RESOURCE_DIR : &str = "../../res";
@@ -58,8 +58,8 @@ fn main() {
 fn run() -> Result<(), Box<dyn Error>> {
 
     // Determine static configuration:
-    let res_dir = exe_relative_dir(Path::new(RESOURCE_DIR)).or_else(
-        |e| Err( format!("Unable to locate application resource files: {:?}", e))
+    let res_dir = exe_relative_dir(Path::new(RESOURCE_DIR)).map_err(
+        |e| format!("Unable to locate application resource files: {:?}", e)
     )?;
 
     // Build dynamic config from command-line and default values:
@@ -77,88 +77,69 @@ fn run() -> Result<(), Box<dyn Error>> {
     };
 
     // Initialize application-specific shared state:
-    let app_state = Arc::new(RwLock::new(AppState {
+    let app_state = RwLock::new(AppState {
         req_cnt: 0,
-    }));
+    });
 
     // Configure server finalization via Ctrl-C
     let enabled_til_ctrlc = shttp::set_ctrlc_finalizer(&config);
 
     // Run the server
-    shttp::run(enabled_til_ctrlc, config, move |request|{
-        process_request(request, &app_config, Arc::clone(&app_state))
-    })?;
+    shttp::Server::new(config, build_router()?)
+        .state(app_config)
+        .state(app_state)
+        .layer(RequestCounter)
+        .run(enabled_til_ctrlc)?;
 
     Ok(())
 }
 
 
-/// The HTTP endpoint router. This is called from each request the server receives
-/// and may be called from different threads each time.
-fn process_request(
-    header: &http::Request, app_config: &AppInfo, app_state: Arc<RwLock<AppState>>)
-    -> Result<http::Response, Box<dyn Error>>
-{
-    use shttp::http:: {
-        req::Method::*,
-        Response,
-        res::Status,
-        res::Content::*,
-    };
+/// Middleware updating the app state for each request, from any of the server threads.
+struct RequestCounter;
+
+impl Middleware for RequestCounter {
+    fn before(&self, request: &mut Request) -> Result<Option<Response>, Box<dyn Error>> {
+        let mut state = request.state::<RwLock<AppState>>().ok_or("Missing app state")?.write().unwrap();
+        state.req_cnt += 1;
+        info!("Request #{}", state.req_cnt);
+        Ok(None)
+    }
+}
+
 
+/// Defines the HTTP endpoints. Their handlers are called from each request the server
+/// receives and may be called from different threads each time.
+fn build_router() -> Result<Router, Box<dyn Error>> {
     use std::time::SystemTime;
     use std::time::Duration;
     use std::thread;
 
-    // Update app state
-    let mut req_cnt = app_state.read().unwrap().req_cnt;
-    req_cnt += 1;
-    info!("Request #{req_cnt}");
-    app_state.write().unwrap().req_cnt = req_cnt;
+    let router = Router::builder()
 
-    // Resolve route:
-    let response = match &header.method {
+        .get("/", |_| Response::new(Status::OK, ServerFile("hello.html".into())))
 
-        Get(uri) if uri == "/" => Response {
-            status: Status::OK,
-            content: ServerFile("hello.html".into()),
-        },
+        .get("/info", |request| {
+            let app_config = request.state::<AppInfo>().ok_or("Missing app info")?;
+            let req_cnt = request.state::<RwLock<AppState>>().ok_or("Missing app state")?.read().unwrap().req_cnt;
+            Ok( format!("{}\nVersion: {}\nRequests: {req_cnt}", app_config.name, app_config.version) )
+        })
 
-        Get(uri) if uri == "/info" => Response {
-            status: Status::OK,
-            content: Text( format!("{}\nVersion: {}\nRequests: {req_cnt}", app_config.name, app_config.version) ),
-        },
-
-        Get(uri) if uri == "/time" => {
+        .get("/time", |_| {
             let unix_time = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
-            Response {
-                status: Status::OK,
-                content: Text( format!("Unix time: {}", unix_time.as_secs()) ),
-            }
-        },
-
-        Get(uri) if uri == "/go" => Response {
-            status: Status::OK,
-            content: ServerFile("hello.html".into()),
-        },
-
-        Get(uri) if uri == "/sleep" => {
+            format!("Unix time: {}", unix_time.as_secs())
+        })
+
+        .get("/go", |_| Response::new(Status::OK, ServerFile("hello.html".into())))
+
+        .get("/sleep", |_| {
             thread::sleep(Duration::from_secs(5));
-            Response {
-                status: Status::OK,
-                content: ServerFile("hello.html".into()),
-            }
-        },
-
-        _ => {
-            Response {
-                status: Status::NotFound,
-                content: UnknownRoute,
-            }
-        },
-    };
+            Response::new(Status::OK, ServerFile("hello.html".into()))
+        })
+
+        .build()?;
 
-    Ok(response)
+    Ok(router)
 }
 
 
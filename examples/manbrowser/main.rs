@@ -1,4 +1,4 @@
-/// Web app example that allows browsing man pages.
+//! Web app example that allows browsing man pages.
 
 // Standard modules
 use std::error::Error;
@@ -6,13 +6,11 @@ use std::process;
 use std::env;
 
 // crates.io modules
-use env_logger;
 use log::{error, debug};
 use clap::{Parser, Args, CommandFactory, FromArgMatches as _};
 
 // The module this example is for
-use shttp;
-use shttp::{ServerConfig, Request, Response, Status, Content, Method};
+use shttp::{ServerConfig, Server, Compression, Request, Response, Status, Content, Method};
 
 // Modules specific to this example
 mod man_reader;
@@ -35,7 +33,7 @@ struct AppConfig {
 fn main() {
 
     // Configure logging
-    if let Ok(_) = env::var("RUST_LOG") {} else {
+    if env::var("RUST_LOG").is_err() {
         // Set default log level if not given in the environment.
         env::set_var("RUST_LOG", DEFAULT_LOG_LEVEL);
     }
@@ -67,7 +65,10 @@ fn run() -> Result<(), Box<dyn Error>> {
     debug!("Srv: {:?}", srv_config);
 
     let enabled = shttp::set_ctrlc_finalizer(&srv_config);
-    shttp::run(enabled, srv_config, router)?;
+    // Rendered pages are large HTML documents, worth compressing:
+    Server::new(srv_config, router)
+        .layer(Compression::new())
+        .run(enabled)?;
 
     Ok(())
 }
@@ -84,10 +85,7 @@ fn router(req: &Request) -> Result<Response, Box<dyn Error>> {
             let msg = format!("Failed to execute `man` command: {:?}", e);
             error!("{}", msg);
 
-            Ok(Response {
-                status:  Status::InternalError,
-                content: Content::Text(msg),
-            })
+            Ok(Response::new(Status::InternalError, Content::text(msg)))
         }
     }
 }
@@ -95,30 +93,29 @@ fn router(req: &Request) -> Result<Response, Box<dyn Error>> {
 
 fn route_manpage(req: &Request) -> Result<Response, Box<dyn Error>> {
 
-    let Method::Get(ref uri) = req.method else {
-        return Ok(Response {
-            status: Status::BadRequest,
-            content: Content::Text("Only the GET method is supported".into()),
-        });
-    };
+    // The server omits the body for HEAD requests.
+    if !matches!(req.method, Method::Get | Method::Head) {
+        return Ok(Response::new(
+            Status::BadRequest,
+            Content::text("Only the GET and HEAD methods are supported")
+        ));
+    }
+
+    let uri = &req.path;
 
-    if uri.len() < 1 {
-        return Ok(Response {
-            status: Status::BadRequest,
-            content: Content::Text("Input too short".into()),
-        });
+    if uri.is_empty() {
+        return Ok(Response::new(Status::BadRequest, Content::text("Input too short")));
     }
 
     let page_name = &uri[1..];
 
     let man_html = match Reader::Pandoc.man_as_html(page_name)? {
-        ManOut::ManPage(html) => html,
-        ManOut::NotFound(msg) => format!("<div class='error'>{}</div>", msg),
+        ManOut::ManStream(html) => Content::Stream(html),
+        ManOut::ManPage(html) => Content::text(html),
+        ManOut::NotFound(msg) => Content::text(format!("<div class='error'>{}</div>", msg)),
     };
 
-    Ok(Response {
-        status:  Status::OK,
-        content: Content::Text(man_html),
-    })
+    Ok(Response::new(Status::OK, man_html)
+        .with_header("Content-Type", "text/html; charset=utf-8")?)
 }
 
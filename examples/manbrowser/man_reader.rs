@@ -1,23 +1,26 @@
-/// Man page reader and formatter to HTML.
-/// External formatter tools are required.
-///
-/// Example:
-/// ```
-/// use man_reader::Reader;
-/// match Reader::Man2Html.man_as_html("bash") {
-///     ManPage(html) => println!(html),
-///     NotFound(_)   => eprintln!("Man page not found"),
-/// };
-/// ```
+//! Man page reader and formatter to HTML.
+//! External formatter tools are required.
+//!
+//! Example:
+//! ```
+//! use man_reader::Reader;
+//! match Reader::Man2Html.man_as_html("bash") {
+//!     ManPage(html) => println!(html),
+//!     NotFound(_)   => eprintln!("Man page not found"),
+//! };
+//! ```
 
 use std::error::Error;
-use std::process::Command;
+use std::io::{self, Read};
+use std::process::{Child, Command, Stdio};
 
 use clap::ValueEnum;
 
 
 pub enum ManOut {
     ManPage(String),
+    /// The formatted page, read while the formatter produces it.
+    ManStream(Box<dyn Read + Send>),
     NotFound(String),
 }
 
@@ -75,10 +78,18 @@ impl Reader {
 
 impl<'a> ManReader<'a> {
 
-    /// Returns a man page formated as HTML
+    /// Returns a man page formated as HTML. Output that needs no post-processing is
+    /// streamed from the formatter, instead of being read into memory first.
     pub fn man_as_html(&self, page_name: &str) -> Result<ManOut, Box<dyn Error>> {
+        if self.filter_cmd == "man2html" {
+            self.man_as_html_buffered(page_name)
+        } else {
+            self.man_as_html_stream(page_name)
+        }
+    }
 
-        use std::process::Stdio;
+    /// Returns a man page formated as HTML, read completely into memory.
+    fn man_as_html_buffered(&self, page_name: &str) -> Result<ManOut, Box<dyn Error>> {
 
         let mut man_cmd = Command::new("man")
             .args(self.man_args)
@@ -103,8 +114,8 @@ impl<'a> ManReader<'a> {
         if self.filter_cmd == "man2html" {
             // Discard HTML headers
             let mut lines = out_str.lines();
-            while let Some(line) = lines.next() {
-                if line == "" { break; }
+            for line in lines.by_ref() {
+                if line.is_empty() { break; }
             }
             out_str = lines.collect();
         }
@@ -120,5 +131,63 @@ impl<'a> ManReader<'a> {
             Ok( ManOut::NotFound(out_str) )
         }
     }
+
+    /// Returns a man page formated as HTML, as a stream read directly from the formatter
+    /// output.
+    fn man_as_html_stream(&self, page_name: &str) -> Result<ManOut, Box<dyn Error>> {
+
+        // Look the page up first, as errors cannot be reported once streaming started.
+        let lookup = Command::new("man")
+            .arg("-w")
+            .arg(page_name)
+            .stdin(Stdio::null())
+            .output()?;
+
+        if !lookup.status.success() {
+            return Ok( ManOut::NotFound(String::from_utf8(lookup.stderr)?) );
+        }
+
+        let mut man_cmd = Command::new("man")
+            .args(self.man_args)
+            .arg(page_name)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let man_out  = man_cmd.stdout.take().ok_or("Cannot read stdout from `man`")?;
+
+        let filter_cmd = Command::new(self.filter_cmd)
+            .args(self.filter_args)
+            .stdin(Stdio::from(man_out))
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        Ok( ManOut::ManStream(Box::new(PipeOutput { man_cmd, filter_cmd })) )
+    }
 }
 
+
+/// Output of the `man | filter` pipeline; both processes are reaped when dropped.
+struct PipeOutput {
+    man_cmd: Child,
+    filter_cmd: Child,
+}
+
+impl Read for PipeOutput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.filter_cmd.stdout.as_mut() {
+            Some(stdout) => stdout.read(buf),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for PipeOutput {
+    fn drop(&mut self) {
+        // Closing the pipe first makes an unfinished filter terminate.
+        drop(self.filter_cmd.stdout.take());
+        let _ = self.filter_cmd.wait();
+        let _ = self.man_cmd.wait();
+    }
+}
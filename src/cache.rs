@@ -0,0 +1,198 @@
+//! Caching policies, sent as the `Cache-Control` and `Vary` header fields.
+//!
+//! Policies are set per response (`Response::with_cache()`), per route (`CachePolicy::apply_to()`)
+//! or by default for each kind of content (`CacheDefaults`, set with `Server::cache_defaults()`).
+//! Example, caching hashed assets for a year while leaving the rest to the defaults:
+//! ```no_run
+//! # fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use shttp::{CachePolicy, Router, StaticFiles};
+//!
+//! let router = Router::builder()
+//!     .mount("/assets", CachePolicy::max_age(365 * 24 * 3600).immutable()
+//!         .apply_to(StaticFiles::new("assets")?))
+//!     .get("/time", |_| format!("{:?}", std::time::SystemTime::now()))
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::error::Error;
+
+use crate::handler::Handler;
+use crate::http::{HeaderMap, Request, Response};
+
+
+/// How clients and intermediaries may store and reuse a response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CachePolicy {
+    no_store: bool,
+    no_cache: bool,
+    max_age: Option<u64>,
+    private: bool,
+    immutable: bool,
+    vary: Vec<String>,
+}
+
+impl CachePolicy {
+
+    /// The response must not be stored at all (`no-store`), e.g. for sensitive data.
+    pub fn no_store() -> CachePolicy {
+        CachePolicy { no_store: true, ..CachePolicy::default() }
+    }
+
+    /// The response may be stored, but must be revalidated with the server before each use
+    /// (`no-cache`), which is cheap for responses with an `ETag` or `Last-Modified`.
+    pub fn no_cache() -> CachePolicy {
+        CachePolicy { no_cache: true, ..CachePolicy::default() }
+    }
+
+    /// The response may be reused without revalidation for `seconds` (`max-age`).
+    pub fn max_age(seconds: u64) -> CachePolicy {
+        CachePolicy { max_age: Some(seconds), ..CachePolicy::default() }
+    }
+
+    /// Only the client may store the response, not shared caches like proxies (`private`).
+    pub fn private(mut self) -> CachePolicy {
+        self.private = true;
+        self
+    }
+
+    /// The response never changes while fresh, so clients need not revalidate it even on
+    /// reload (`immutable`), e.g. for assets with a content hash in their name.
+    pub fn immutable(mut self) -> CachePolicy {
+        self.immutable = true;
+        self
+    }
+
+    /// The response depends on the request header field `name` (e.g. `Accept-Language`), so
+    /// caches must keep a separate response for each of its values (`Vary`).
+    pub fn vary(mut self, name: &str) -> CachePolicy {
+        if !self.vary.iter().any(|known| known.eq_ignore_ascii_case(name)) {
+            self.vary.push(name.to_string());
+        }
+        self
+    }
+
+    /// Returns the value of the `Cache-Control` field, if the policy has any directive.
+    pub fn cache_control(&self) -> Option<String> {
+        let mut directives = vec![];
+        if self.private {
+            directives.push("private".to_string());
+        }
+        if self.no_store {
+            directives.push("no-store".to_string());
+        }
+        if self.no_cache {
+            directives.push("no-cache".to_string());
+        }
+        if let Some(max_age) = self.max_age {
+            directives.push(format!("max-age={max_age}"));
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        (!directives.is_empty()).then(|| directives.join(", "))
+    }
+
+    /// Sets the policy in `headers`, replacing any `Cache-Control` and adding to `Vary`.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Some(cache_control) = self.cache_control() {
+            headers.insert("Cache-Control", cache_control);
+        }
+        for name in &self.vary {
+            if !headers.contains_token("Vary", name) {
+                headers.append("Vary", name.as_str());
+            }
+        }
+    }
+
+    /// Sets the policy in `headers`, unless they have a `Cache-Control` already.
+    pub fn apply_default(&self, headers: &mut HeaderMap) {
+        if !headers.contains("Cache-Control") {
+            self.apply(headers);
+        }
+    }
+
+    /// Wraps `handler` (e.g. a route), so that its successful (2xx) responses get this policy
+    /// unless they set their own. Other responses are left to the server defaults.
+    pub fn apply_to<H: Handler>(self, handler: H) -> Cached<H> {
+        Cached { policy: self, handler }
+    }
+}
+
+
+/// A handler whose responses get a default caching policy (see `CachePolicy::apply_to()`).
+#[derive(Debug)]
+pub struct Cached<H> {
+    policy: CachePolicy,
+    handler: H,
+}
+
+impl<H: Handler> Handler for Cached<H> {
+    fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        let mut response = self.handler.handle(request)?;
+        if response.status.is_success() {
+            self.policy.apply_default(&mut response.headers);
+        }
+        Ok(response)
+    }
+}
+
+
+/// Policies for responses that set none, by the kind of their content.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheDefaults {
+    /// For files (`Content::ServerFile` and `Content::UserFile`), which have validators to
+    /// revalidate them. Defaults to `no-cache`.
+    pub files: CachePolicy,
+    /// For content generated by handlers (`Content::Bytes` and `Content::Stream`), including
+    /// error responses. Defaults to `no-store`.
+    pub generated: CachePolicy,
+}
+
+impl Default for CacheDefaults {
+    fn default() -> CacheDefaults {
+        CacheDefaults { files: CachePolicy::no_cache(), generated: CachePolicy::no_store() }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{CacheDefaults, CachePolicy};
+    use crate::handler::Handler;
+    use crate::http::{HeaderMap, Request, Response, res::{Content, Status}};
+
+    #[test]
+    fn test_cache_policy() {
+        let year = CachePolicy::max_age(31536000).immutable();
+        assert_eq!(year.cache_control().as_deref(), Some("max-age=31536000, immutable"));
+        let private = CachePolicy::no_cache().private().vary("Cookie").vary("cookie");
+        assert_eq!(private.cache_control().as_deref(), Some("private, no-cache"));
+        assert_eq!(CacheDefaults::default().generated.cache_control().as_deref(), Some("no-store"));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("Vary", "Accept-Encoding, Cookie");
+        private.vary("Accept-Language").apply(&mut headers);
+        assert_eq!(headers.get_list("Vary"), ["Accept-Encoding", "Cookie", "Accept-Language"]);
+        year.apply_default(&mut headers);
+        assert_eq!(headers.get("Cache-Control"), Some("private, no-cache"));
+
+        let route = year.apply_to(|request: &Request| {
+            let mut response = Response::new(Status::OK, Content::text(""));
+            match request.path.as_str() {
+                "/own"      => response.headers.insert("Cache-Control", "no-store"),
+                "/missing"  => response.status = Status::NotFound,
+                _           => {},
+            }
+            response
+        });
+        let cache_control = |path: &str| {
+            let request = Request::parse(&format!("GET {path} HTTP/1.1\r\n")).unwrap();
+            route.handle(&request).unwrap().headers.get("Cache-Control").map(String::from)
+        };
+        assert_eq!(cache_control("/app.3f2a.js").as_deref(), Some("max-age=31536000, immutable"));
+        assert_eq!(cache_control("/own").as_deref(), Some("no-store"));
+        assert_eq!(cache_control("/missing"), None);
+    }
+}
@@ -0,0 +1,300 @@
+//! Compression of response content, in a content coding negotiated with `Accept-Encoding`.
+//!
+//! Example, compressing the responses of a router:
+//! ```no_run
+//! # fn example(config: shttp::ServerConfig, router: shttp::Router) {
+//! use shttp::{Compression, Server};
+//!
+//! let server = Server::new(config, router).layer(Compression::new());
+//! # }
+//! ```
+
+use std::error::Error;
+use std::io::{Cursor, Read};
+
+use crate::http::{HeaderMap, Request, Response, res::{Content, Status}};
+use crate::middleware::Middleware;
+
+
+/// Default minimum size of the content to compress, in bytes.
+const DEFAULT_MIN_SIZE: u64 = 1024;
+
+/// Default compression level.
+const DEFAULT_LEVEL: u32 = 6;
+
+/// Media types (or prefixes of them) of content that is compressed already.
+const COMPRESSED_TYPES: &[&str] = &[
+    "image/", "audio/", "video/", "font/woff",
+    "application/gzip", "application/x-gzip", "application/zip", "application/epub+zip",
+    "application/x-bzip2", "application/x-xz", "application/zstd", "application/x-7z-compressed",
+    "application/pdf", "application/wasm", "multipart/byteranges",
+];
+
+
+/// Content codings, in the order they are preferred when the client accepts several equally.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// Brotli (`br`), with the `brotli` feature.
+    #[cfg(feature = "brotli")]
+    Brotli,
+    /// Gzip (`gzip`).
+    Gzip,
+    /// The zlib format (`deflate`).
+    Deflate,
+}
+
+impl Encoding {
+
+    /// All the supported codings, by preference.
+    pub const ALL: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        Encoding::Gzip,
+        Encoding::Deflate,
+    ];
+
+    /// Returns the name of the coding, as in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli    => "br",
+            Encoding::Gzip      => "gzip",
+            Encoding::Deflate   => "deflate",
+        }
+    }
+
+    /// Returns a reader producing the content of `reader` encoded, at `level` (0-9).
+    fn encoder(&self, reader: Box<dyn Read + Send>, level: u32) -> Box<dyn Read + Send> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli    => Box::new(brotli::CompressorReader::new(reader, 4096, level, 22)),
+            Encoding::Gzip      =>
+                Box::new(flate2::read::GzEncoder::new(reader, flate2::Compression::new(level))),
+            Encoding::Deflate   =>
+                Box::new(flate2::read::ZlibEncoder::new(reader, flate2::Compression::new(level))),
+        }
+    }
+}
+
+
+/// Returns the preferred coding among those accepted by the elements of an `Accept-Encoding`
+/// field (like `gzip;q=0.8`), if any is accepted with a non-zero quality.
+pub fn negotiate(accept_encoding: &[&str]) -> Option<Encoding> {
+    let mut best: Option<(Encoding, f32)> = None;
+    for &encoding in Encoding::ALL {
+        let quality = quality(accept_encoding, encoding.name());
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+
+/// Returns the quality (from 0, not accepted, to 1) given to the content coding `name` by the
+/// elements of an `Accept-Encoding` field, either explicitly or through `*`.
+pub fn quality(accept_encoding: &[&str], name: &str) -> f32 {
+    let qualities: Vec<(&str, f32)> = accept_encoding.iter()
+        .filter_map(|element| {
+            let mut params = element.split(';').map(str::trim);
+            let name = params.next()?;
+            let quality = match params.find_map(|param| param.strip_prefix("q=")) {
+                Some(quality) => quality.parse().ok().filter(|q| (0.0..=1.0).contains(q))?,
+                None => 1.0,
+            };
+            Some((name, quality))
+        })
+        .collect();
+    let find = |name: &str| qualities.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(name))
+        .map(|(_, quality)| *quality);
+
+    let alias = if name == "gzip" { "x-gzip" } else { name };
+    find(name).or_else(|| find(alias)).or_else(|| find("*")).unwrap_or(0.0)
+}
+
+
+/// Middleware compressing the content of responses, when the client accepts it (see
+/// `negotiate()`) and it is worth it.
+///
+/// Content given as `Content::Bytes` or `Content::Stream` is compressed (streams on the fly,
+/// without a length) unless it is smaller than the minimum size, of a media type compressed
+/// already (like images), already encoded, or marked `Cache-Control: no-transform`. File
+/// content is sent as is, so that it can still be requested by ranges.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: u64,
+    level: u32,
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression { min_size: DEFAULT_MIN_SIZE, level: DEFAULT_LEVEL }
+    }
+}
+
+impl Compression {
+
+    /// Creates the layer with the default minimum size (1 KiB) and level (6).
+    pub fn new() -> Compression {
+        Compression::default()
+    }
+
+    /// Sets the minimum size of the content to compress, in bytes. Streams of unknown length
+    /// are always compressed.
+    pub fn with_min_size(mut self, min_size: u64) -> Compression {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Sets the compression level, from 0 (fastest) to 9 (smallest).
+    pub fn with_level(mut self, level: u32) -> Compression {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Returns `true` if the content of `response` is worth compressing.
+    fn is_compressible(&self, response: &Response) -> bool {
+        let headers = &response.headers;
+        let length = match &response.content {
+            Content::Bytes(bytes) => Some(bytes.len() as u64),
+            Content::Stream(_) =>
+                headers.get("Content-Length").and_then(|length| length.parse().ok()),
+            _ => return false,
+        };
+
+        let no_content = response.status.is_informational() || matches!(
+            response.status, Status::NoContent | Status::NotModified | Status::PartialContent
+        );
+        let compressed_type = headers.get("Content-Type").is_some_and(|media_type| {
+            let media_type = media_type.trim().to_ascii_lowercase();
+            !media_type.starts_with("image/svg+xml")
+                && COMPRESSED_TYPES.iter().any(|known| media_type.starts_with(known))
+        });
+
+        !no_content
+            && !compressed_type
+            && length.is_none_or(|length| length >= self.min_size)
+            && !headers.contains("Content-Encoding")
+            && !headers.contains("Content-Range")
+            && !headers.contains_token("Cache-Control", "no-transform")
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, request: &Request, mut response: Response) -> Result<Response, Box<dyn Error>> {
+        if !self.is_compressible(&response) {
+            return Ok(response);
+        }
+        // The content depends on the field even when not compressed, e.g. for shared caches.
+        add_vary(&mut response.headers);
+
+        let Some(encoding) = negotiate(&request.headers.get_list("Accept-Encoding")) else {
+            return Ok(response);
+        };
+
+        response.content = match response.content {
+            Content::Bytes(bytes) => {
+                let mut encoded = vec![];
+                encoding.encoder(Box::new(Cursor::new(bytes)), self.level)
+                    .read_to_end(&mut encoded)?;
+                Content::Bytes(encoded)
+            },
+            Content::Stream(reader) => {
+                response.headers.remove("Content-Length");
+                Content::Stream(encoding.encoder(reader, self.level))
+            },
+            content => content,
+        };
+        response.headers.insert("Content-Encoding", encoding.name());
+
+        // The encoded content is not byte for byte the one a strong tag identifies.
+        if let Some(etag) = response.headers.get("ETag").filter(|etag| !etag.starts_with("W/")) {
+            let etag = format!("W/{etag}");
+            response.headers.insert("ETag", etag);
+        }
+        Ok(response)
+    }
+}
+
+
+/// Adds `Accept-Encoding` to the `Vary` field of `headers`.
+fn add_vary(headers: &mut HeaderMap) {
+    if !headers.contains_token("Vary", "Accept-Encoding") && !headers.contains_token("Vary", "*") {
+        headers.append("Vary", "Accept-Encoding");
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::{negotiate, Compression, Encoding};
+    use crate::http::{Request, Response, res::{Content, Status}};
+    use crate::middleware::Middleware;
+
+    #[test]
+    fn test_negotiate() {
+        let preferred = Encoding::ALL[0];
+        let cases = [
+            ("gzip, deflate",                   Some(Encoding::Gzip)),
+            ("deflate;q=0.5, gzip;q=0.8",       Some(Encoding::Gzip)),
+            ("x-gzip",                          Some(Encoding::Gzip)),
+            ("deflate, *;q=0",                  Some(Encoding::Deflate)),
+            ("*",                               Some(preferred)),
+            ("gzip;q=0, deflate;q=0, br;q=0",   None),
+            ("identity",                        None),
+            ("gzip;q=2",                        None),
+            ("",                                None),
+        ];
+        for (accept_encoding, expected) in cases {
+            let elements: Vec<&str> = accept_encoding.split(',').map(str::trim).collect();
+            assert_eq!(negotiate(&elements), expected, "{accept_encoding}");
+        }
+    }
+
+    #[test]
+    fn test_compression() {
+        let html = "<p>Some text</p>\n".repeat(100);
+        let compress = |accept_encoding: &str, media_type: &str, content: Content| {
+            let header = format!("GET / HTTP/1.1\r\nAccept-Encoding: {accept_encoding}\r\n");
+            let request = Request::parse(&header).unwrap();
+            let mut response = Response::new(Status::OK, content);
+            response.headers.insert("Content-Type", media_type);
+            response.headers.insert("ETag", "\"v1\"");
+            Compression::new().after(&request, response).unwrap()
+        };
+        let decode = |content: Content| {
+            let mut decoded = String::new();
+            match content {
+                Content::Bytes(bytes) => GzDecoder::new(&bytes[..]).read_to_string(&mut decoded),
+                Content::Stream(reader) => GzDecoder::new(reader).read_to_string(&mut decoded),
+                _ => unreachable!(),
+            }.unwrap();
+            decoded
+        };
+
+        let response = compress("gzip", "text/html", Content::text(html.clone()));
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"v1\""));
+        assert_eq!(decode(response.content), html);
+
+        let stream = Content::Stream(Box::new(std::io::Cursor::new(html.clone())));
+        let response = compress("gzip", "text/html", stream);
+        assert_eq!(decode(response.content), html);
+
+        let response = compress("identity", "text/html", Content::text(html.clone()));
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        let skipped = [("image/png", Content::text(html.clone())), ("text/html", Content::text("<p>"))];
+        for (media_type, content) in skipped {
+            let response = compress("gzip", media_type, content);
+            assert!(!response.headers.contains("Content-Encoding") && !response.headers.contains("Vary"));
+        }
+    }
+}
@@ -0,0 +1,141 @@
+//! Errors of the server, each answered with its own HTTP status.
+
+use std::fmt;
+use std::io;
+
+use crate::http::{Response, req::BodyError, res::{Content, Status}};
+
+
+/// Errors processing a request.
+///
+/// Handlers return them like any other error (e.g. `Err(Error::status(Status::Forbidden,
+/// "Read only"))?`), and the server answers with the status and message they carry. Other
+/// errors from handlers are answered with `500 Internal Server Error`.
+#[derive(Debug)]
+pub enum Error {
+    /// The request is not valid HTTP (400 Bad Request).
+    Parse(String),
+
+    /// The request header exceeds the maximum size (431 Request Header Fields Too Large).
+    HeaderTooLarge { max_size: usize },
+
+    /// The request body exceeds the maximum size (413 Content Too Large).
+    PayloadTooLarge { length: usize, max_size: usize },
+
+    /// The client did not send the request in time (408 Request Timeout).
+    Timeout,
+
+    /// Reading or writing the connection, or a resource, failed (500 Internal Server Error).
+    Io(io::Error),
+
+    /// An error from a handler, with its own status and a message sent as the content.
+    Handler { status: Status, message: String },
+}
+
+impl Error {
+
+    /// Creates an error answered with `status` and `message` as its content.
+    pub fn status(status: Status, message: impl Into<String>) -> Error {
+        Error::Handler { status, message: message.into() }
+    }
+
+    /// Returns the status the error is answered with.
+    pub fn to_status(&self) -> Status {
+        use Error::*;
+
+        match self {
+            Parse(_)                => Status::BadRequest,
+            HeaderTooLarge { .. }   => Status::RequestHeaderFieldsTooLarge,
+            PayloadTooLarge { .. }  => Status::PayloadTooLarge,
+            Timeout                 => Status::RequestTimeout,
+            Io(_)                   => Status::InternalError,
+            Handler { status, .. }  => status.clone(),
+        }
+    }
+
+    /// Returns the response for the error. Client errors are described in the content, while
+    /// the details of server errors are not disclosed.
+    pub fn to_response(&self) -> Response {
+        let status = self.to_status();
+        let message = match self {
+            Error::Handler { message, .. } => message.clone(),
+            _ if status.is_client_error() => self.to_string(),
+            _ => status.reason().to_string(),
+        };
+        Response::new(status, Content::text(message))
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg) => write!(f, "Bad request: {msg}"),
+            Error::HeaderTooLarge { max_size } =>
+                write!(f, "Request header exceeds the maximum of {max_size} bytes"),
+            Error::PayloadTooLarge { length, max_size } =>
+                write!(f, "Request body of {length} bytes exceeds the maximum of {max_size} bytes"),
+            Error::Timeout => write!(f, "Request timeout"),
+            Error::Io(error) => write!(f, "I/O error: {error}"),
+            Error::Handler { status, message } => write!(f, "{status}: {message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Error {
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(error),
+        }
+    }
+}
+
+impl From<BodyError> for Error {
+    fn from(error: BodyError) -> Error {
+        match error {
+            BodyError::Malformed(msg) => Error::Parse(format!("Malformed request body: {msg}")),
+            BodyError::TooLarge { length, max_size } => Error::PayloadTooLarge { length, max_size },
+            BodyError::Timeout => Error::Timeout,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Error;
+    use crate::http::{req::BodyError, res::{Content, Status}};
+
+    #[test]
+    fn test_error_responses() {
+        let cases = [
+            (Error::Parse("Invalid method".into()), Status::BadRequest, "Bad request: Invalid method"),
+            (Error::HeaderTooLarge { max_size: 8 }, Status::RequestHeaderFieldsTooLarge,
+                "Request header exceeds the maximum of 8 bytes"),
+            (BodyError::TooLarge { length: 9, max_size: 8 }.into(), Status::PayloadTooLarge,
+                "Request body of 9 bytes exceeds the maximum of 8 bytes"),
+            (io::Error::from(io::ErrorKind::WouldBlock).into(), Status::RequestTimeout, "Request timeout"),
+            (BodyError::Timeout.into(), Status::RequestTimeout, "Request timeout"),
+            (io::Error::other("disk on fire").into(), Status::InternalError, "Internal Server Error"),
+            (Error::status(Status::Forbidden, "Read only"), Status::Forbidden, "Read only"),
+        ];
+
+        for (error, status, message) in cases {
+            let response = error.to_response();
+            assert_eq!(response.status, status);
+            assert!(matches!(&response.content, Content::Bytes(bytes) if bytes == message.as_bytes()),
+                "{error}");
+        }
+    }
+}
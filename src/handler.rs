@@ -0,0 +1,136 @@
+//! Request handlers: anything that turns a `Request` into a `Response`.
+
+use std::error::Error;
+
+use crate::http::{Request, Response, res::{Content, Status}};
+
+
+/// A request handler, as run by the server for each request it receives (possibly from
+/// different threads at the same time).
+///
+/// It is implemented for closures and functions taking a `&Request` and returning any
+/// `IntoResponse` type, and for library types like `Router`.
+pub trait Handler: Send + Sync + 'static {
+
+    /// Processes `request` and returns its response.
+    fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>>;
+}
+
+
+impl<F, R> Handler for F
+where
+    F: Fn(&Request) -> R + Send + Sync + 'static,
+    R: IntoResponse,
+{
+    fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        self(request).into_response()
+    }
+}
+
+
+/// Values that handlers can return as a response:
+///
+/// - A `Response`, as is.
+/// - Text (`String` or `&'static str`) or bytes (`Vec<u8>`), as the content of a `200 OK`.
+/// - A `(Status, T)` pair, as the response for `T` with that status instead.
+/// - An `Option<T>`, with `None` as `404 Not Found` (`Content::UnknownRoute`).
+/// - A `Result<T, Box<dyn Error>>`, with errors answered by the server (with the status of
+///   an `shttp::Error`, or `500 Internal Server Error` for others). Other error types are
+///   converted with `?`; accepting them here too would leave the error type of closures like
+///   `|_| Ok("text")` ambiguous.
+pub trait IntoResponse {
+
+    fn into_response(self) -> Result<Response, Box<dyn Error>>;
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        Ok(self)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        Ok(Response::new(Status::OK, Content::text(self)))
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        Ok(Response::new(Status::OK, Content::text(self)))
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        Ok(Response::new(Status::OK, Content::Bytes(self)))
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for (Status, T) {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        let (status, value) = self;
+        Ok(Response { status, ..value.into_response()? })
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Option<T> {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        match self {
+            Some(value) => value.into_response(),
+            None => Ok(Response::new(Status::NotFound, Content::UnknownRoute)),
+        }
+    }
+}
+
+impl<T: IntoResponse> IntoResponse for Result<T, Box<dyn Error>> {
+    fn into_response(self) -> Result<Response, Box<dyn Error>> {
+        self?.into_response()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+
+    use super::{Handler, IntoResponse};
+    use crate::http::{Request, Response, res::{Content, Status}};
+
+    /// Returns the status and the content (`None` for non-byte content) of a response.
+    fn parts(value: impl IntoResponse) -> (Status, Option<Vec<u8>>) {
+        let response = value.into_response().unwrap();
+        match response.content {
+            Content::Bytes(bytes) => (response.status, Some(bytes)),
+            _ => (response.status, None),
+        }
+    }
+
+    #[test]
+    fn test_into_response() {
+        assert_eq!(parts("text"), (Status::OK, Some(b"text".to_vec())));
+        assert_eq!(parts(String::from("text")), (Status::OK, Some(b"text".to_vec())));
+        assert_eq!(parts(vec![0_u8, 255]), (Status::OK, Some(vec![0, 255])));
+        assert_eq!(parts((Status::Created, "new")), (Status::Created, Some(b"new".to_vec())));
+        assert_eq!(parts(Some("found")), (Status::OK, Some(b"found".to_vec())));
+        assert_eq!(parts(None::<String>), (Status::NotFound, None));
+        assert_eq!(parts(Ok::<_, Box<dyn Error>>("ok")), (Status::OK, Some(b"ok".to_vec())));
+        assert_eq!(
+            parts(Response::new(Status::NoContent, Content::Bytes(vec![]))),
+            (Status::NoContent, Some(vec![]))
+        );
+
+        let failed: Result<&str, Box<dyn Error>> = Err("failed".into());
+        assert!(failed.into_response().is_err_and(|error| error.to_string() == "failed"));
+
+        // The error type of closures is inferred:
+        let handler = |request: &Request| {
+            let id = request.query.get("id").ok_or("Missing id")?;
+            Ok((Status::Accepted, format!("id={id}")))
+        };
+        let request = Request::parse("GET /?id=7 HTTP/1.1\r\n").unwrap();
+        assert_eq!(handler.handle(&request).unwrap().status, Status::Accepted);
+        let request = Request::parse("GET / HTTP/1.1\r\n").unwrap();
+        assert!(handler.handle(&request).is_err());
+    }
+}
@@ -1,254 +1,247 @@
+use std::error::Error;
+use std::fmt;
 
-pub type Request = req::Request;
+pub type Request<'a> = req::Request<'a>;
 pub type Response = res::Response;
 
 /// HTTP Request
-pub mod req {
-
-    use std::error::Error;
-    use std::net::TcpStream;
-    use std::io::prelude::*;
-    use std::collections::HashMap;
-    use crate::uri;
-
-    const HTTP_HEADER_MAX_LEN : usize = 1024 * 1;
-
-    /// HTTP Request Methods
-    #[derive(Debug)]
-    pub enum Method {
-        Get(String),
-        Put(String),
-    }
-
-
-    /// HTTP Request Header
-    #[derive(Debug)]
-    pub struct Request {
-        pub method:     Method,
-        pub headers:    HashMap::<String, String>,
-        pub warnings:   Vec::<String>,
-    }
-
-
-    impl Request {
-
-        /// Parses an HTTP header given as a raw string and returns the corresponding
-        /// `Request` object.
-        pub fn parse(header: &str) -> Result<Request, Box<dyn Error>> {
-
-            let mut warnings: Vec<String> = vec![];
-            let mut lines = header.lines();
-
-            // First line in the header is the URI request.
-
-            let method = if let Some(request) = lines.next() {
-                // First line has the URI request
-                let fields: Vec<_> = request.split_ascii_whitespace().collect();
-
-                let [method_field, raw_uri, http_version] = fields[..] else {
-                    return Err("Missing fields in URI in header.")?;
-                };
-
-                if http_version != "HTTP/1.1" {
-                    warnings.push(format!("Unknown HTTP version {}", http_version));
-                }
-
-                let Ok(uri) = uri::decode_uri(raw_uri) else {
-                    return Err("Encoded URL does not represent valid UTF-8: {raw_uri}")?;
-                };
-
-                match method_field.to_ascii_uppercase().as_str() {
-                    "GET" => Method::Get(uri),
-                    "PUT" => Method::Put(uri),
-                    _ => return Err(
-                        format!("Unknown HTTP method: {}", method_field).into()
-                    ),
-                }
-            }
-            else {
-                return Err("Could not find URI in header.".into());
-            };
-
-            // Remaining lines in the header are HTTP header fields.
-           
-            let mut headers = HashMap::<String, String>::new();
-
-            for line in lines {
-                let colon_pair: Vec<_> = line.splitn(2, ':').collect();
-                
-                if let [name, value] = colon_pair[..] {
-                    headers.insert(name.trim().into(), value.trim().into());
-                }
-                else {
-                    warnings.push(format!(
-                        "Invalid header line, missing colon separator in: '{line}'"
-                    ));
-                }
-            }
-
-            Ok(Request { method, headers, warnings })
-        }
+pub mod req;
+
+/// HTTP Response
+pub mod res;
+
+/// Dates and times
+pub mod date;
+
+/// Conditional requests
+pub mod conditional;
+
+/// Byte ranges
+pub mod range;
+
 
+/// HTTP Protocol Version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
 
-        pub fn parse_from_stream(stream: &mut TcpStream) ->
-            Result<Request, Box<dyn Error>>
-        {
-            let request_header = retrieve_header(stream)?;
-            Request::parse(&request_header[..])
+    /// Parses a protocol version as found in a request line (e.g. `HTTP/1.1`).
+    pub fn parse(version: &str) -> Option<Version> {
+        match version {
+            "HTTP/1.0" => Some(Version::Http10),
+            "HTTP/1.1" => Some(Version::Http11),
+            _ => None,
         }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        })
+    }
+}
 
-    } // impl Request
 
+/// HTTP Header Fields
+///
+/// Names are compared case-insensitively but kept as given, and every value is kept in the
+/// order it was added, so repeated fields like `Cookie` or `Set-Cookie` are not lost.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HeaderMap {
+    fields: Vec<(String, String)>,
+}
 
-    fn retrieve_header(stream: &mut TcpStream) -> Result<String, Box<dyn Error>> {
-        // Look at most the first 1KB
-        let mut buf = [0; HTTP_HEADER_MAX_LEN];
-        let _len = stream.peek(&mut buf)?;
+impl HeaderMap {
 
-        let buf_str = String::from_utf8_lossy(&buf);
+    pub fn new() -> HeaderMap {
+        HeaderMap::default()
+    }
 
-        for terminator in [ "\r\n\r\n", "\n\n" ] {
+    /// Returns the first value of the field `name`.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields.iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 
-            if let Some(end_index) = buf_str.find(terminator) {
+    /// Returns all the values of the field `name`, in order.
+    pub fn get_all<'h>(&'h self, name: &'h str) -> impl Iterator<Item = &'h str> {
+        self.fields.iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 
-                // Get the header
-                let mut head_buf = Vec::with_capacity(end_index);
-                head_buf.resize(end_index, 0);
-                stream.read_exact(&mut head_buf[..])?;
+    /// Returns the elements of a comma-separated list field (like `Accept` or `Connection`),
+    /// gathered from all its values, trimmed and without empty elements.
+    pub fn get_list<'h>(&'h self, name: &'h str) -> Vec<&'h str> {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|element| !element.is_empty())
+            .collect()
+    }
 
-                // Discard separator
-                let mut _sep_buf = Vec::with_capacity(terminator.len());
-                _sep_buf.resize(terminator.len(), 0);
-                stream.read_exact(&mut _sep_buf)?;
+    /// Returns `true` if the list field `name` contains `token`, compared case-insensitively
+    /// (e.g. `Connection: close`).
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_list(name).iter().any(|element| element.eq_ignore_ascii_case(token))
+    }
 
-                return Ok(String::from_utf8_lossy(&head_buf).to_string());
-            }
-        }
+    /// Returns `true` if the field `name` is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
 
-        // No terminator matched:
-        Err( format!(
-            "Could not find header terminator in the first {HTTP_HEADER_MAX_LEN} \
-             bytes. Header: {buf_str}"
-        ).into())
+    /// Adds a value for the field `name`, keeping any previous ones.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.fields.push((name.into(), value.into()));
     }
 
-} // mod Request
+    /// Sets `value` as the only value of the field `name`.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.fields.push((name, value.into()));
+    }
 
+    /// Removes all the values of the field `name`; returns `true` if there was any.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.fields.len();
+        self.fields.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+        self.fields.len() != len
+    }
 
-/// HTTP Response
-pub mod res {
-
-    use std::path::PathBuf;
-    use std::fs;
-    use log::error;
-
-    /// HTTP Response Status
-    pub enum Status {
-        OK,
-        BadRequest,
-        NotFound,
-        InternalError,
-    }
-
-    impl Status {
-
-        pub fn as_str(&self) -> &'static str {
-            use Status::*;
-            match self {
-                OK              => "HTTP/1.1 200 OK",
-                BadRequest      => "HTTP/1.1 400 BAD REQUEST",
-                NotFound        => "HTTP/1.1 404 NOT FOUND",
-                InternalError   => "HTTP/1.1 500 INTERNAL SERVER ERROR",
-            }
-        }
+    /// Like `append()`, but rejects names that are not valid tokens and values with control
+    /// characters (which could be used to inject extra header lines).
+    pub fn try_append(&mut self, name: impl Into<String>, value: impl Into<String>)
+        -> Result<(), InvalidHeader>
+    {
+        let (name, value) = (name.into(), value.into());
+        validate_header(&name, &value)?;
+        self.append(name, value);
+        Ok(())
     }
 
+    /// Like `insert()`, but validated as in `try_append()`.
+    pub fn try_insert(&mut self, name: impl Into<String>, value: impl Into<String>)
+        -> Result<(), InvalidHeader>
+    {
+        let (name, value) = (name.into(), value.into());
+        validate_header(&name, &value)?;
+        self.insert(name, value);
+        Ok(())
+    }
 
-    /// The actual HTTP response data to send
-    pub struct TextResponse {
-        pub status: Status,
-        pub body: String,
+    /// Returns all the fields as (name, value) pairs, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.fields.iter().map(|(name, value)| (name.as_str(), value.as_str()))
     }
 
-    impl TextResponse {
-        pub fn as_string(&self) -> String {
-            // FIXME: Avoid copying `body`, perhaps by returning a string iterator.
-            let status_str = self.status.as_str();
-            let mut response = format!("{}\r\nContent-Length: {}\r\nCache-Control: no-store, no-cache, must-revalidate\r\n\r\n", status_str, self.body.len());
-            response.push_str(&self.body);
-            response
-        }
+    /// Number of field values.
+    pub fn len(&self) -> usize {
+        self.fields.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+}
+
+
+/// Checks that a header field can be safely serialized: `name` must be a token and `value`
+/// must not contain control characters other than horizontal tabs (notably no CR or LF).
+pub fn validate_header(name: &str, value: &str) -> Result<(), InvalidHeader> {
+    if !is_token(name) {
+        return Err(InvalidHeader::Name(name.into()));
+    }
+    if has_control_chars(value) {
+        return Err(InvalidHeader::Value(name.into()));
+    }
+    Ok(())
+}
+
+
+/// Returns `true` if `text` contains control characters other than horizontal tabs, which
+/// field values and reason phrases must not contain.
+pub(crate) fn has_control_chars(text: &str) -> bool {
+    text.chars().any(|c| c.is_ascii_control() && c != '\t')
+}
+
+
+/// Returns `true` if `string` is a valid HTTP token (as used for method and header names).
+pub(crate) fn is_token(string: &str) -> bool {
+    !string.is_empty() && string.bytes().all(|b|
+        b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+    )
+}
+
 
-    /// HTTP Response Content
-    pub enum Content {
-        ServerFile(PathBuf),
-        UserFile(PathBuf),
-        Text(String),
-        UnknownRoute,
-        // TODO: Maybe add `Stream`?
-    }
-
-    /// HTTP response get from routers
-    pub struct Response {
-        pub status: Status,
-        pub content: Content,
-    }
-
-
-    impl Response {
-
-        pub fn into_text_response(self, server_path: &PathBuf) -> TextResponse {
-
-            use Content::*;
-
-            let mut response = self;
-
-            loop {
-                // Transform `response` until we get `Text`
-                response = match response.content {
-
-                    Text(text) => return TextResponse {
-                        status: response.status,
-                        body: text,
-                    },
-
-                    UserFile(abs_path) => {
-                        match fs::read_to_string(&abs_path)
-                        {
-                            Ok(file_text) => Response {
-                                status:  response.status,
-                                content: Text(file_text),
-                            },
-                            Err(e) => {
-                                error!("Failed to read '{:?}': {:?}", abs_path, e);
-                                Response {
-                                    status: Status::InternalError,
-                                    content: Text("Resource not available.".into()),
-                                }
-                            },
-                        }
-                    },
-
-                    ServerFile(rel_path) => {
-                        let mut abs_path = server_path.clone();
-                        abs_path.push(rel_path);
-                        Response {
-                            status: response.status,
-                            content: UserFile(abs_path),
-                        }
-                    },
-
-                    UnknownRoute => Response {
-                        status: Status::NotFound,
-                        content: ServerFile("404.html".into()),
-                    },
-                };
-            } // loop
-            // The compiler knows this point is `unreachable!()`.
-        } // fn
-
-    } // impl
+/// Error for header fields that would produce an invalid (or injected) HTTP message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvalidHeader {
+    /// The field name is not a valid token.
+    Name(String),
+    /// The value of the named field contains control characters.
+    Value(String),
 }
 
+impl fmt::Display for InvalidHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidHeader::Name(name) => write!(f, "Invalid header name: {name:?}"),
+            InvalidHeader::Value(name) => write!(f, "Invalid value for header {name:?}"),
+        }
+    }
+}
+
+impl Error for InvalidHeader {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{HeaderMap, InvalidHeader};
+
+    #[test]
+    fn test_header_map() {
+        let mut headers = HeaderMap::new();
+        headers.append("Accept", "text/html, application/json");
+        headers.append("cookie", "a=1");
+        headers.append("ACCEPT", "*/*;q=0.1");
+        headers.append("Cookie", "b=2");
+
+        assert_eq!(headers.get("accept"), Some("text/html, application/json"));
+        assert_eq!(headers.get_all("COOKIE").collect::<Vec<_>>(), ["a=1", "b=2"]);
+        assert_eq!(headers.get_list("Accept"), ["text/html", "application/json", "*/*;q=0.1"]);
+        assert!(headers.contains_token("accept", "TEXT/HTML"));
+        assert!(!headers.contains("Host"));
+
+        headers.insert("cookie", "c=3");
+        assert_eq!(headers.get_all("Cookie").collect::<Vec<_>>(), ["c=3"]);
+        assert_eq!(headers.len(), 3);
+
+        assert!(headers.remove("Accept"));
+        assert!(!headers.remove("Accept"));
+        assert_eq!(headers.iter().collect::<Vec<_>>(), [("cookie", "c=3")]);
+    }
+
+    #[test]
+    fn test_header_validation() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(headers.try_append("X-Custom", "a\tb c"), Ok(()));
+        assert_eq!(
+            headers.try_append("X-Custom", "a\r\nSet-Cookie: evil=1"),
+            Err(InvalidHeader::Value("X-Custom".into()))
+        );
+        assert_eq!(headers.try_insert("Bad Name", "x"), Err(InvalidHeader::Name("Bad Name".into())));
+        assert_eq!(headers.try_insert("Bad:Name", "x"), Err(InvalidHeader::Name("Bad:Name".into())));
+        assert_eq!(headers.try_insert("", "x"), Err(InvalidHeader::Name("".into())));
+        assert_eq!(headers.len(), 1);
+    }
+}
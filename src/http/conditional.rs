@@ -0,0 +1,214 @@
+//! Conditional requests: the `If-*` header fields compared with the validators of a response
+//! (`ETag` and `Last-Modified`), answered with `304 Not Modified` or `412 Precondition Failed`.
+
+use super::{HeaderMap, date::DateTime, req::{Method, Request}, res::{Body, Status, TextResponse}};
+
+
+/// Result of evaluating the preconditions of a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precondition {
+    /// No precondition, or all of them hold: the request is processed as usual.
+    Passed,
+    /// The client has the current representation (`304 Not Modified`, for `GET` and `HEAD`).
+    NotModified,
+    /// A precondition does not hold (`412 Precondition Failed`).
+    Failed,
+}
+
+
+/// Evaluates the preconditions of `request` against the current representation of the
+/// target, identified by its entity tag `etag` (as sent in `ETag`, quotes included) and/or
+/// its modification date, following the order of RFC 9110 (section 13.2.2).
+///
+/// Handlers changing state (e.g. `PUT`) must call it before acting, since the server only
+/// evaluates the preconditions of `GET` and `HEAD` requests, against the response they return.
+pub fn check(request: &Request, etag: Option<&str>, last_modified: Option<DateTime>) -> Precondition {
+    let headers = &request.headers;
+    let is_read = matches!(request.method, Method::Get | Method::Head);
+
+    if let Some(if_match) = field_list(headers, "If-Match") {
+        if !matches_any(&if_match, etag, true) {
+            return Precondition::Failed;
+        }
+    }
+    else if let Some(since) = headers.get("If-Unmodified-Since").and_then(DateTime::parse_http_date) {
+        if last_modified.is_some_and(|last_modified| last_modified > since) {
+            return Precondition::Failed;
+        }
+    }
+
+    if let Some(if_none_match) = field_list(headers, "If-None-Match") {
+        if matches_any(&if_none_match, etag, false) {
+            return if is_read { Precondition::NotModified } else { Precondition::Failed };
+        }
+    }
+    else if let Some(since) = headers.get("If-Modified-Since").and_then(DateTime::parse_http_date) {
+        if is_read && last_modified.is_some_and(|last_modified| last_modified <= since) {
+            return Precondition::NotModified;
+        }
+    }
+
+    Precondition::Passed
+}
+
+
+/// Evaluates the preconditions of a `GET` or `HEAD` `request` against the validators of a
+/// successful `response`, replacing it by a `304` or `412` response when they call for it.
+///
+/// Other methods are left alone: their handler has acted already, so it is up to it to call
+/// `check()` first.
+pub fn evaluate(request: &Request, response: TextResponse) -> TextResponse {
+    if !matches!(request.method, Method::Get | Method::Head) || !response.status.is_success() {
+        return response;
+    }
+
+    let etag = response.headers.get("ETag");
+    let last_modified = response.headers.get("Last-Modified").and_then(DateTime::parse_http_date);
+
+    match check(request, etag, last_modified) {
+        Precondition::Passed => response,
+
+        // Keeps the fields a cache needs to update its stored response.
+        Precondition::NotModified => {
+            let mut headers = response.headers;
+            remove_content_fields(&mut headers);
+            TextResponse { status: Status::NotModified, headers, body: Body::Omitted { length: None } }
+        },
+
+        Precondition::Failed =>
+            TextResponse::new(Status::PreconditionFailed, "Precondition failed".into()),
+    }
+}
+
+
+/// Removes the metadata of the content (`Content-*` fields but `Content-Location`) from the
+/// headers of a response answered without it.
+pub(super) fn remove_content_fields(headers: &mut HeaderMap) {
+    let content_fields: Vec<String> = headers.iter()
+        .map(|(name, _)| name.to_string())
+        .filter(|name| name.get(..8).is_some_and(|prefix| prefix.eq_ignore_ascii_case("Content-"))
+            && !name.eq_ignore_ascii_case("Content-Location"))
+        .collect();
+    for name in content_fields {
+        headers.remove(&name);
+    }
+}
+
+
+/// Returns all the values of the field `name` as a single list, if present.
+fn field_list(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers.contains(name).then(|| headers.get_all(name).collect::<Vec<_>>().join(","))
+}
+
+
+/// Returns `true` if the list of entity tags `list` matches `etag`, with the strong comparison
+/// (both tags strong and equal) or else the weak one (equal, ignoring `W/`). `*` matches any
+/// current representation, with or without a tag.
+fn matches_any(list: &str, etag: Option<&str>, strong: bool) -> bool {
+    if list.trim() == "*" {
+        return true;
+    }
+    let Some(etag) = etag else {
+        return false;
+    };
+
+    let (etag_weak, etag_opaque) = split_weak(etag);
+    let mut rest = list;
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ',']);
+        if rest.is_empty() {
+            return false;
+        }
+        // Tags are quoted, and may contain commas.
+        let (weak, tagged) = split_weak(rest);
+        let Some(end) = tagged.strip_prefix('"').and_then(|tail| tail.find('"')) else {
+            return false;
+        };
+        let opaque = &tagged[..end + 2];
+        if opaque == etag_opaque && !(strong && (weak || etag_weak)) {
+            return true;
+        }
+        rest = &tagged[end + 2..];
+    }
+}
+
+
+/// Splits an entity tag into whether it is weak and its opaque (quoted) part.
+fn split_weak(etag: &str) -> (bool, &str) {
+    match etag.strip_prefix("W/") {
+        Some(opaque) => (true, opaque),
+        None => (false, etag),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{check, evaluate, Precondition};
+    use crate::http::{date::DateTime, req::Request, res::{Body, Status, TextResponse}};
+
+    fn request(method: &str, fields: &str) -> Request<'static> {
+        Request::parse(&format!("{method} /file HTTP/1.1\r\n{fields}")).unwrap()
+    }
+
+    #[test]
+    fn test_check() {
+        use Precondition::*;
+
+        let etag = Some("\"1a-2b\"");
+        let date = DateTime::parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT");
+        let cases = [
+            ("GET",     "",                                                 Passed),
+            ("GET",     "If-None-Match: \"x\", W/\"1a-2b\"",                NotModified),
+            ("HEAD",    "If-None-Match: *",                                 NotModified),
+            ("GET",     "If-None-Match: \"x,y\"",                           Passed),
+            ("PUT",     "If-None-Match: *",                                 Failed),
+            ("GET",     "If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT", NotModified),
+            ("GET",     "If-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT", Passed),
+            ("GET",     "If-Modified-Since: yesterday",                     Passed),
+            ("GET",     "If-None-Match: \"x\"\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT", Passed),
+            ("PUT",     "If-Match: \"1a-2b\"",                              Passed),
+            ("PUT",     "If-Match: W/\"1a-2b\"",                            Failed),
+            ("PUT",     "If-Match: \"x\"\r\nIf-Match: \"1a-2b\"",           Passed),
+            ("PUT",     "If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT", Failed),
+            ("GET",     "If-Match: \"x\"\r\nIf-None-Match: \"1a-2b\"",      Failed),
+        ];
+        for (method, fields, expected) in cases {
+            assert_eq!(check(&request(method, fields), etag, date), expected, "{method} {fields}");
+        }
+        assert_eq!(check(&request("PUT", "If-Match: *"), None, None), Passed);
+        assert_eq!(check(&request("PUT", "If-Match: \"1a-2b\""), None, None), Failed);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let response = || {
+            let mut response = TextResponse::new(Status::OK, "content".into());
+            response.headers.insert("Content-Type", "text/plain");
+            response.headers.insert("ETag", "\"v1\"");
+            response
+        };
+
+        let not_modified = evaluate(&request("GET", "If-None-Match: \"v1\""), response());
+        assert_eq!(not_modified.status, Status::NotModified);
+        assert_eq!(not_modified.headers.get("ETag"), Some("\"v1\""));
+        assert!(!not_modified.headers.contains("Content-Type"));
+        assert!(matches!(not_modified.body, Body::Omitted { .. }));
+
+        let failed = evaluate(&request("GET", "If-Match: \"v2\""), response());
+        assert_eq!(failed.status, Status::PreconditionFailed);
+
+        // `*` matches content without validators too.
+        let untagged = || TextResponse::new(Status::OK, "content".into());
+        assert_eq!(evaluate(&request("GET", "If-Match: *"), untagged()).status, Status::OK);
+        assert_eq!(evaluate(&request("GET", "If-None-Match: *"), untagged()).status, Status::NotModified);
+
+        // Handlers of other methods have acted already, and check preconditions themselves.
+        let created = TextResponse::new(Status::Created, "".into());
+        assert_eq!(evaluate(&request("PUT", "If-None-Match: *"), created).status, Status::Created);
+        assert_eq!(evaluate(&request("POST", "If-Match: \"x\""), untagged()).status, Status::OK);
+
+        let not_found = TextResponse::new(Status::NotFound, "".into());
+        assert_eq!(evaluate(&request("GET", "If-Match: \"v2\""), not_found).status, Status::NotFound);
+    }
+}
@@ -0,0 +1,166 @@
+//! Dates and times in UTC, without time zone databases.
+
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+/// A UTC date and time, with a precision of seconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+
+    /// Converts a system time, truncated to seconds. Times before 1970 are taken as 1970.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        DateTime::from_unix_time(secs)
+    }
+
+    /// Converts a number of seconds since 1970-01-01 00:00:00 UTC.
+    pub fn from_unix_time(secs: u64) -> DateTime {
+        let (days, secs_of_day) = ((secs / 86400) as i64, (secs % 86400) as u32);
+
+        // Civil date from days since the epoch, counting 400 year eras from 0000-03-01
+        // (Howard Hinnant's algorithm).
+        let z = days + 719468;
+        let era = z.div_euclid(146097);
+        let day_of_era = z.rem_euclid(146097);
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153; // From March
+        let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+        let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year, month, day,
+            hour: secs_of_day / 3600,
+            minute: secs_of_day / 60 % 60,
+            second: secs_of_day % 60,
+        }
+    }
+
+    /// Returns the number of seconds since 1970-01-01 00:00:00 UTC (negative before it).
+    pub fn to_unix_time(&self) -> i64 {
+        // Inverse of `from_unix_time()`.
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let month_index = i64::from((self.month + 9) % 12);
+        let day_of_year = (153 * month_index + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146097 + day_of_era - 719468;
+
+        days * 86400 + i64::from(self.hour * 3600 + self.minute * 60 + self.second)
+    }
+
+    /// Converts back to a system time (times before 1970 are taken as 1970).
+    pub fn to_system_time(&self) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(self.to_unix_time().max(0) as u64)
+    }
+
+    /// Formats as an HTTP date (IMF-fixdate), e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+    pub fn to_http_date(&self) -> String {
+        let weekday = (self.to_unix_time().div_euclid(86400) + 4).rem_euclid(7); // 1970-01-01 was a Thursday
+        format!("{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[weekday as usize], self.day, MONTHS[self.month as usize - 1], self.year,
+            self.hour, self.minute, self.second)
+    }
+
+    /// Parses an HTTP date in any of the formats recipients must accept: IMF-fixdate
+    /// (`Sun, 06 Nov 1994 08:49:37 GMT`), RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and
+    /// asctime (`Sun Nov  6 08:49:37 1994`). The day of the week is not checked.
+    pub fn parse_http_date(text: &str) -> Option<DateTime> {
+        let fields: Vec<&str> = text.split_whitespace().collect();
+        let (day, month, year, time) = match fields[..] {
+            [_, day, month, year, time, "GMT"] => (day, month, year.parse().ok()?, time),
+            [_, date, time, "GMT"] => {
+                let mut parts = date.split('-');
+                let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+                // Two-digit years are taken to be within 1970-2069.
+                let year: i64 = year.parse().ok()?;
+                let year = if year >= 100 { year } else if year >= 70 { 1900 + year } else { 2000 + year };
+                (day, month, year, time)
+            },
+            [_, month, day, time, year] => (day, month, year.parse().ok()?, time),
+            _ => return None,
+        };
+
+        let mut time = time.split(':').map(|part| part.parse::<u32>().ok());
+        let date = DateTime {
+            year,
+            month: MONTHS.iter().position(|name| *name == month)? as u32 + 1,
+            day: day.parse().ok()?,
+            hour: time.next()??,
+            minute: time.next()??,
+            second: time.next()??,
+        };
+
+        // Rejects leftovers and out of range fields (like `Feb 30` or `24:00:00`), bounding
+        // them before the conversion so that it cannot overflow:
+        let valid = time.next().is_none()
+            && (1970..=9999).contains(&date.year)
+            && (1..=31).contains(&date.day)
+            && date.hour <= 23 && date.minute <= 59 && date.second <= 59
+            && DateTime::from_unix_time(date.to_unix_time() as u64) == date;
+        valid.then_some(date)
+    }
+}
+
+/// Formats as `YYYY-MM-DD HH:MM:SS`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::DateTime;
+
+    #[test]
+    fn test_unix_time() {
+        let cases = [
+            (0,             "1970-01-01 00:00:00"),
+            (951782400,     "2000-02-29 00:00:00"),
+            (1700000000,    "2023-11-14 22:13:20"),
+            (4107542399,    "2100-02-28 23:59:59"),
+        ];
+        for (secs, formatted) in cases {
+            let date = DateTime::from_unix_time(secs);
+            assert_eq!(date.to_string(), formatted);
+            assert_eq!(date.to_unix_time(), secs as i64);
+        }
+    }
+
+    #[test]
+    fn test_http_date() {
+        let date = DateTime::from_unix_time(784111777);
+        assert_eq!(date.to_http_date(), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(DateTime::from_unix_time(1700000000).to_http_date(), "Tue, 14 Nov 2023 22:13:20 GMT");
+
+        for text in ["Sun, 06 Nov 1994 08:49:37 GMT", "Sunday, 06-Nov-94 08:49:37 GMT", "Sun Nov  6 08:49:37 1994"] {
+            assert_eq!(DateTime::parse_http_date(text), Some(date), "{text}");
+        }
+        for text in ["Sun, 30 Feb 1994 08:49:37 GMT", "Sun, 06 Nov 1994 24:00:00 GMT", "Sun, 06 Nov 1994 08:49 GMT",
+            "Sun, 06 Nov 1994 08:49:37 CET", "06 Nov 1994", "", "Sun, 06 Nov 1994 4294967295:00:00 GMT",
+            "Sun, 06 Nov 1994 08:4294967295:37 GMT", "Sun, 06 Nov 1994 08:60:37 GMT", "Sun, 00 Nov 1994 08:49:37 GMT",
+            "Sun, 4294967295 Nov 1994 08:49:37 GMT", "Sun, 06 Nov 9223372036854775807 08:49:37 GMT",
+            "Sun, 06 Nov 10000 08:49:37 GMT"]
+        {
+            assert_eq!(DateTime::parse_http_date(text), None, "{text}");
+        }
+    }
+}
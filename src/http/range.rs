@@ -0,0 +1,343 @@
+//! Byte ranges: parts of file content requested with `Range` (and `If-Range`), answered with
+//! `206 Partial Content` or `416 Range Not Satisfiable`.
+
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::{HeaderMap, conditional, date::DateTime, req::{Method, Request}, res::{Body, Status, TextResponse}};
+
+
+/// Maximum number of ranges in a request; requests with more are answered with the whole
+/// content, so that they cannot make the server send it many times over.
+const MAX_RANGES: usize = 16;
+
+
+/// A range of bytes, from `start` to `end` (both included).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+
+    /// Number of bytes in the range.
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+
+/// A range as given in a `Range` field, before knowing the length of the content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeSpec {
+    /// `first-last` or `first-`.
+    FromTo(u64, Option<u64>),
+    /// `-length`: the last `length` bytes.
+    Suffix(u64),
+}
+
+
+/// Parses the value of a `Range` field, returning `None` if it is not a valid list of byte
+/// ranges (then the field is ignored).
+fn parse_ranges(value: &str) -> Option<Vec<RangeSpec>> {
+    let (unit, ranges) = value.split_once('=')?;
+    if !unit.trim().eq_ignore_ascii_case("bytes") {
+        return None;
+    }
+
+    let mut specs = vec![];
+    for range in ranges.split(',').map(str::trim).filter(|range| !range.is_empty()) {
+        let (first, last) = range.split_once('-')?;
+        let number = |text: &str| -> Option<u64> {
+            text.bytes().all(|b| b.is_ascii_digit()).then(|| text.parse().ok()).flatten()
+        };
+        let spec = match (first, last) {
+            ("", suffix)    => RangeSpec::Suffix(number(suffix)?),
+            (first, "")     => RangeSpec::FromTo(number(first)?, None),
+            (first, last)   => {
+                let (first, last) = (number(first)?, number(last)?);
+                if last < first {
+                    return None;
+                }
+                RangeSpec::FromTo(first, Some(last))
+            },
+        };
+        specs.push(spec);
+    }
+    (!specs.is_empty()).then_some(specs)
+}
+
+
+/// Returns the satisfiable ranges among `specs` for content of `length` bytes, sorted and
+/// with overlapping or adjacent ranges merged.
+fn resolve_ranges(specs: &[RangeSpec], length: u64) -> Vec<ByteRange> {
+    let mut ranges: Vec<ByteRange> = specs.iter()
+        .filter_map(|spec| match *spec {
+            RangeSpec::FromTo(start, _) if start >= length => None,
+            RangeSpec::FromTo(start, end) =>
+                Some(ByteRange { start, end: end.unwrap_or(u64::MAX).min(length - 1) }),
+            // Empty content has no last bytes to send.
+            RangeSpec::Suffix(suffix) if suffix == 0 || length == 0 => None,
+            RangeSpec::Suffix(suffix) =>
+                Some(ByteRange { start: length.saturating_sub(suffix), end: length - 1 }),
+        })
+        .collect();
+
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<ByteRange> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(1) =>
+                last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+
+/// Returns `true` if the `If-Range` field of the request (if any) allows answering with a
+/// part of `response`: it must be the strong entity tag or the modification date of the
+/// content.
+fn if_range_holds(request: &Request, response: &TextResponse) -> bool {
+    let Some(if_range) = request.headers.get("If-Range") else {
+        return true;
+    };
+    if if_range.starts_with('"') {
+        return response.headers.get("ETag") == Some(if_range);
+    }
+    if if_range.starts_with("W/") {
+        return false;
+    }
+    let date = DateTime::parse_http_date(if_range);
+    date.is_some() && response.headers.get("Last-Modified").and_then(DateTime::parse_http_date) == date
+}
+
+
+/// Answers the `Range` field of a `GET` request for file content with the parts requested,
+/// when they are satisfiable and allowed by `If-Range`. File responses also tell that ranges
+/// are supported, with `Accept-Ranges`.
+pub fn evaluate(request: &Request, mut response: TextResponse) -> TextResponse {
+    if response.status != Status::OK || !matches!(response.body, Body::File { .. }) {
+        return response;
+    }
+    response.headers.insert("Accept-Ranges", "bytes");
+
+    let Some(specs) = request.headers.get("Range").and_then(parse_ranges) else {
+        return response;
+    };
+    if request.method != Method::Get
+        || specs.len() > MAX_RANGES
+        || !if_range_holds(request, &response)
+    {
+        return response;
+    }
+
+    let Body::File { file, length } = response.body else {
+        unreachable!("Checked above");
+    };
+
+    let ranges = resolve_ranges(&specs, length);
+    let result = match ranges[..] {
+        // Keeps the validators and caching fields, which describe the whole content.
+        [] => {
+            let mut headers = response.headers;
+            conditional::remove_content_fields(&mut headers);
+            headers.insert("Content-Range", format!("bytes */{length}"));
+            return TextResponse { status: Status::RangeNotSatisfiable, headers, body: "".into() };
+        },
+        [range] => single_part(file, range, length, &mut response.headers),
+        _ => multiple_parts(file, &ranges, length, &mut response.headers),
+    };
+
+    match result {
+        Ok(body) => TextResponse { status: Status::PartialContent, headers: response.headers, body },
+        Err(e) => {
+            log::error!("Failed to read file range: {e}");
+            TextResponse::new(Status::InternalError, "Resource not available.".into())
+        },
+    }
+}
+
+
+/// Returns the body with the part `range` of `file`, setting its `Content-Range`.
+fn single_part(mut file: fs::File, range: ByteRange, length: u64, headers: &mut HeaderMap)
+    -> io::Result<Body>
+{
+    file.seek(SeekFrom::Current(range.start as i64))?;
+    let content_range = format!("bytes {}-{}/{length}", range.start, range.end);
+    headers.insert("Content-Range", content_range);
+    Ok(Body::File { file, length: range.len() })
+}
+
+
+/// Returns a `multipart/byteranges` body with the parts `ranges` of `file`, each with the
+/// media type of the whole content.
+fn multiple_parts(mut file: fs::File, ranges: &[ByteRange], length: u64, headers: &mut HeaderMap)
+    -> io::Result<Body>
+{
+    let media_type = headers.get("Content-Type").map(String::from);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+    let boundary = format!("shttp-{:016x}", nanos as u64);
+
+    let mut parts = VecDeque::new();
+    let mut body_length = 0;
+    for range in ranges {
+        let mut part_header = format!("\r\n--{boundary}\r\n");
+        if let Some(media_type) = &media_type {
+            part_header.push_str(&format!("Content-Type: {media_type}\r\n"));
+        }
+        part_header.push_str(&format!(
+            "Content-Range: bytes {}-{}/{length}\r\n\r\n", range.start, range.end
+        ));
+        body_length += part_header.len() as u64 + range.len();
+        parts.push_back(Part::Bytes(Cursor::new(part_header.into_bytes())));
+        parts.push_back(Part::Range(*range));
+    }
+    let end = format!("\r\n--{boundary}--\r\n");
+    body_length += end.len() as u64;
+    parts.push_back(Part::Bytes(Cursor::new(end.into_bytes())));
+
+    headers.insert("Content-Type", format!("multipart/byteranges; boundary={boundary}"));
+    // Like its length, the ranges are relative to the current position of the file.
+    let offset = file.stream_position()?;
+    let reader = PartsReader { file, offset, parts, remaining: None };
+    Ok(Body::Stream { reader: Box::new(reader), length: Some(body_length) })
+}
+
+
+/// A piece of a `multipart/byteranges` body.
+enum Part {
+    Bytes(Cursor<Vec<u8>>),
+    Range(ByteRange),
+}
+
+/// Reads the parts of a `multipart/byteranges` body, seeking in the file for each range.
+struct PartsReader {
+    file: fs::File,
+    offset: u64,
+    parts: VecDeque<Part>,
+    /// Bytes left of the range being read.
+    remaining: Option<u64>,
+}
+
+impl Read for PartsReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if let Some(remaining) = self.remaining {
+                if remaining > 0 {
+                    let max = buf.len().min(remaining.try_into().unwrap_or(usize::MAX));
+                    let len = self.file.read(&mut buf[..max])?;
+                    if len == 0 {
+                        return Err(io::ErrorKind::UnexpectedEof.into());
+                    }
+                    self.remaining = Some(remaining - len as u64);
+                    return Ok(len);
+                }
+                self.remaining = None;
+                self.parts.pop_front();
+                continue;
+            }
+
+            match self.parts.front_mut() {
+                None => return Ok(0),
+                Some(Part::Bytes(cursor)) => {
+                    let len = cursor.read(buf)?;
+                    if len > 0 || buf.is_empty() {
+                        return Ok(len);
+                    }
+                    self.parts.pop_front();
+                },
+                Some(Part::Range(range)) => {
+                    self.file.seek(SeekFrom::Start(self.offset + range.start))?;
+                    self.remaining = Some(range.len());
+                },
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::Read;
+
+    use super::{evaluate, parse_ranges, resolve_ranges, ByteRange, RangeSpec};
+    use crate::http::{req::Request, res::{Body, Status, TextResponse}};
+    use crate::test_dir::TestDir;
+
+    #[test]
+    fn test_ranges() {
+        use RangeSpec::*;
+
+        assert_eq!(parse_ranges("bytes=0-499, 500-, -200"),
+            Some(vec![FromTo(0, Some(499)), FromTo(500, None), Suffix(200)]));
+        for invalid in ["bytes=5-1", "bytes=-", "bytes=a-b", "bytes=+1-2", "items=0-1", "bytes=", "0-1"] {
+            assert_eq!(parse_ranges(invalid), None, "{invalid}");
+        }
+
+        let range = |start, end| ByteRange { start, end };
+        let resolve = |value| resolve_ranges(&parse_ranges(value).unwrap(), 1000);
+        assert_eq!(resolve("bytes=0-0,-1"), [range(0, 0), range(999, 999)]);
+        assert_eq!(resolve("bytes=900-2000"), [range(900, 999)]);
+        assert_eq!(resolve("bytes=-2000"), [range(0, 999)]);
+        assert_eq!(resolve("bytes=500-599,0-99,100-199,550-"), [range(0, 199), range(500, 999)]);
+        assert_eq!(resolve("bytes=1000-,-0"), []);
+        assert_eq!(resolve_ranges(&parse_ranges("bytes=-1,0-").unwrap(), 0), []);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let dir = TestDir::new("range");
+        let path = dir.join("file.txt");
+        fs::write(&path, "0123456789").unwrap();
+
+        let get = |fields: &str| {
+            let request = Request::parse(&format!("GET /file HTTP/1.1\r\n{fields}")).unwrap();
+            let body = Body::File { file: fs::File::open(&path).unwrap(), length: 10 };
+            let mut response = TextResponse::new(Status::OK, body);
+            response.headers.insert("Content-Type", "text/plain");
+            response.headers.insert("ETag", "\"v1\"");
+            let response = evaluate(&request, response);
+            let mut content = String::new();
+            match response.body {
+                Body::File { file, length } => file.take(length).read_to_string(&mut content),
+                Body::Stream { reader, length } => reader.take(length.unwrap()).read_to_string(&mut content),
+                Body::Bytes(bytes) => bytes.as_slice().read_to_string(&mut content),
+                Body::Omitted { .. } => unreachable!(),
+            }.unwrap();
+            (response.status, response.headers, content)
+        };
+
+        let (status, headers, content) = get("");
+        assert_eq!((status, headers.get("Accept-Ranges"), content.as_str()),
+            (Status::OK, Some("bytes"), "0123456789"));
+
+        let (status, headers, content) = get("Range: bytes=-3\r\nIf-Range: \"v1\"");
+        assert_eq!((status, headers.get("Content-Range"), content.as_str()),
+            (Status::PartialContent, Some("bytes 7-9/10"), "789"));
+
+        let (status, headers, content) = get("Range: bytes=1-2,5-5");
+        assert_eq!(status, Status::PartialContent);
+        let boundary = headers.get("Content-Type").unwrap()
+            .strip_prefix("multipart/byteranges; boundary=").unwrap();
+        assert_eq!(content, format!(
+            "\r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 1-2/10\r\n\r\n12\
+            \r\n--{boundary}\r\nContent-Type: text/plain\r\nContent-Range: bytes 5-5/10\r\n\r\n5\
+            \r\n--{boundary}--\r\n"));
+
+        let (status, headers, content) = get("Range: bytes=10-");
+        assert_eq!((status, headers.get("Content-Range"), content.as_str()),
+            (Status::RangeNotSatisfiable, Some("bytes */10"), ""));
+        assert_eq!((headers.get("ETag"), headers.get("Accept-Ranges")), (Some("\"v1\""), Some("bytes")));
+        assert!(!headers.contains("Content-Type"));
+
+        assert_eq!(get("Range: bytes=0-1\r\nIf-Range: \"v0\"").0, Status::OK);
+        assert_eq!(get("Range: bytes=0-1\r\nIf-Range: Sun, 06 Nov 1994 08:49:37 GMT").0, Status::OK);
+        assert_eq!(get("Range: bytes=x").0, Status::OK);
+    }
+}
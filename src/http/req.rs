@@ -0,0 +1,1035 @@
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, prelude::*, BufReader};
+use std::sync::Arc;
+use crate::uri;
+use crate::state::State;
+use crate::error::Error as ServerError;
+use super::{HeaderMap, Version, is_token};
+
+/// Maximum size of a request header (request line and header fields).
+const HTTP_HEADER_MAX_LEN : usize = 8 * 1024;
+
+/// HTTP Request Methods
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+    /// Any other method, like WebDAV's `PROPFIND`, with its name as received.
+    Extension(String),
+}
+
+impl Method {
+
+    /// Parses a method name. Standard methods are recognized regardless of case; any other
+    /// valid token is taken as an `Extension` method.
+    pub fn parse(name: &str) -> Result<Method, Box<dyn Error>> {
+        use Method::*;
+
+        Ok(match name.to_ascii_uppercase().as_str() {
+            "GET"       => Get,
+            "HEAD"      => Head,
+            "POST"      => Post,
+            "PUT"       => Put,
+            "DELETE"    => Delete,
+            "CONNECT"   => Connect,
+            "OPTIONS"   => Options,
+            "TRACE"     => Trace,
+            "PATCH"     => Patch,
+            _ if is_token(name) => Extension(name.into()),
+            _ => return Err(format!("Invalid HTTP method: {name}").into()),
+        })
+    }
+
+    /// Returns the method name as sent on the wire.
+    pub fn as_str(&self) -> &str {
+        use Method::*;
+
+        match self {
+            Get         => "GET",
+            Head        => "HEAD",
+            Post        => "POST",
+            Put         => "PUT",
+            Delete      => "DELETE",
+            Connect     => "CONNECT",
+            Options     => "OPTIONS",
+            Trace       => "TRACE",
+            Patch       => "PATCH",
+            Extension(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+
+/// HTTP Request
+#[derive(Debug)]
+pub struct Request<'a> {
+    pub method:     Method,
+    pub version:    Version,
+    /// The request target as received (still percent-encoded).
+    pub target:     String,
+    /// The percent-decoded path of the request target.
+    pub path:       String,
+    /// The decoded query parameters of the request target.
+    pub query:      Query,
+    pub headers:    HeaderMap,
+    pub warnings:   Vec::<String>,
+    pub body:       Body<'a>,
+    /// Parameters captured from the path by a `Router` (see `param()`).
+    params:         RefCell<Vec<(String, String)>>,
+    /// Application state registered in the server (see `state()`).
+    state:          Arc<State>,
+}
+
+
+impl<'a> Request<'a> {
+
+    /// Parses an HTTP header given as a raw string and returns the corresponding
+    /// `Request` object, with an empty body.
+    pub fn parse(header: &str) -> Result<Request<'a>, Box<dyn Error>> {
+
+        let mut warnings: Vec<String> = vec![];
+        let mut lines = header.lines();
+
+        // First line in the header is the URI request.
+
+        let (method, version, target, path, query) = if let Some(request) = lines.next() {
+            // First line has the URI request
+            let fields: Vec<_> = request.split_ascii_whitespace().collect();
+
+            let [method_field, target, http_version] = fields[..] else {
+                return Err("Missing fields in URI in header.")?;
+            };
+
+            // Unknown versions are answered as HTTP/1.1, the latest known one.
+            let version = Version::parse(http_version).unwrap_or_else(|| {
+                warnings.push(format!("Unknown HTTP version {}", http_version));
+                Version::Http11
+            });
+
+            // Split before decoding, so that encoded `?`, `&` or `=` are taken literally.
+            let (raw_path, raw_query) = uri::split_target(target);
+
+            let Ok(path) = uri::decode_uri(raw_path) else {
+                return Err(format!("Encoded URL does not represent valid UTF-8: {target}"))?;
+            };
+            let query = Query::parse(raw_query.unwrap_or_default())?;
+
+            (Method::parse(method_field)?, version, target.to_string(), path, query)
+        }
+        else {
+            return Err("Could not find URI in header.".into());
+        };
+
+        // Remaining lines in the header are HTTP header fields.
+       
+        let mut headers = HeaderMap::new();
+
+        for line in lines {
+            // Obsolete line folding would hide fields from some parsers but not others.
+            if line.starts_with([' ', '\t']) {
+                return Err(format!("Obsolete line folding in header: '{line}'"))?;
+            }
+            let colon_pair: Vec<_> = line.splitn(2, ':').collect();
+            
+            if let [name, value] = colon_pair[..] {
+                // Whitespace before the colon is not allowed either (RFC 9112, section 5.1).
+                if !is_token(name) {
+                    return Err(format!("Invalid header field name: '{name}'"))?;
+                }
+                headers.append(name, value.trim());
+            }
+            else {
+                warnings.push(format!(
+                    "Invalid header line, missing colon separator in: '{line}'"
+                ));
+            }
+        }
+
+        Ok(Request {
+            method, version, target, path, query, headers, warnings, body: Body::empty(),
+            params: RefCell::default(), state: Arc::default(),
+        })
+    }
+
+
+    /// Reads and parses a request from `stream`: the header is consumed and the body is
+    /// either read into memory or left in the stream to be read on demand, according to
+    /// `limits`.
+    pub fn parse_from_stream(stream: &'a mut dyn BufRead, limits: &BodyLimits) ->
+        Result<Request<'a>, ServerError>
+    {
+        let request_header = retrieve_header(stream)?;
+        let mut request = Request::parse(&request_header[..])
+            .map_err(|e| ServerError::Parse(e.to_string()))?;
+
+        request.body = match request.body_framing()? {
+            BodyFraming::Chunked    => Body::read_chunked(stream, limits)?,
+            BodyFraming::Length(n)  => Body::read_from(stream, n, limits)?,
+            BodyFraming::None       => Body::empty(),
+        };
+
+        Ok(request)
+    }
+
+
+    /// Returns `true` if the client asked to keep the connection open after this request:
+    /// the default for HTTP/1.1, and opt-in with `Connection: keep-alive` for HTTP/1.0.
+    pub fn keep_alive(&self) -> bool {
+        match self.version {
+            Version::Http11 => !self.headers.contains_token("Connection", "close"),
+            Version::Http10 => self.headers.contains_token("Connection", "keep-alive"),
+        }
+    }
+
+
+    /// Returns the (decoded) value of the path parameter `name`, as captured by the `Router`
+    /// route that matched this request (e.g. `id` in `/users/:id`).
+    pub fn param(&self, name: &str) -> Option<String> {
+        self.params.borrow().iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.clone())
+    }
+
+    /// Returns all the captured path parameters, as (name, value) pairs.
+    pub fn params(&self) -> Vec<(String, String)> {
+        self.params.borrow().clone()
+    }
+
+    /// Adds captured path parameters; set by the router, which only gets a `&Request`.
+    pub(crate) fn add_params(&self, params: Vec<(String, String)>) {
+        self.params.borrow_mut().extend(params);
+    }
+
+
+    /// Returns the application state of type `T` registered with `Server::state()`, if any.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.state.get()
+    }
+
+    /// Makes the server state available to handlers.
+    pub(crate) fn set_state(&mut self, state: Arc<State>) {
+        self.state = state;
+    }
+
+
+    /// Determines how the body is delimited, from `Transfer-Encoding` and `Content-Length`.
+    fn body_framing(&self) -> Result<BodyFraming, BodyError> {
+        if !self.headers.contains("Transfer-Encoding") {
+            return Ok(match self.content_length()? {
+                Some(length) => BodyFraming::Length(length),
+                None => BodyFraming::None,
+            });
+        }
+
+        // Both headers at once is a classic request smuggling vector, refuse to guess.
+        if self.headers.contains("Content-Length") {
+            return Err(BodyError::Malformed(
+                "Both Transfer-Encoding and Content-Length given".into()
+            ));
+        }
+
+        // `chunked` must be the last (and, as no other coding is supported, the only) coding.
+        let codings = self.headers.get_list("Transfer-Encoding");
+        match codings[..] {
+            [coding] if coding.eq_ignore_ascii_case("chunked") => Ok(BodyFraming::Chunked),
+            _ => Err(BodyError::Malformed(format!(
+                "Unsupported Transfer-Encoding: '{}'", codings.join(", ")
+            ))),
+        }
+    }
+
+
+    /// Returns the body length declared in the `Content-Length` header, if any.
+    fn content_length(&self) -> Result<Option<usize>, BodyError> {
+        let Some(value) = self.headers.get("Content-Length") else {
+            return Ok(None);
+        };
+
+        // Repeated fields are only acceptable if they all agree.
+        if self.headers.get_all("Content-Length").any(|other| other != value) {
+            return Err(BodyError::Malformed("Conflicting Content-Length fields".into()));
+        }
+
+        // Only plain decimal digits are valid (no sign, no spaces, no lists).
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(BodyError::Malformed(format!("Invalid Content-Length: '{value}'")));
+        }
+
+        value.parse::<usize>()
+            .map(Some)
+            .map_err(|_| BodyError::Malformed(format!("Content-Length out of range: {value}")))
+    }
+
+} // impl Request
+
+
+/// How the end of a request body is determined.
+enum BodyFraming {
+    None,
+    Length(usize),
+    Chunked,
+}
+
+
+/// Query parameters of a request: an ordered multimap from keys to values, both decoded.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Query {
+    pairs: Vec<(String, String)>,
+}
+
+impl Query {
+
+    /// Parses a raw query string (without the leading `?`) encoded as
+    /// `application/x-www-form-urlencoded`. Keys without `=` get an empty value.
+    pub fn parse(raw_query: &str) -> Result<Query, Box<dyn Error>> {
+        let mut pairs = Vec::new();
+
+        for field in raw_query.split('&').filter(|field| !field.is_empty()) {
+            let (key, value) = field.split_once('=').unwrap_or((field, ""));
+            let (Ok(key), Ok(value)) = (
+                uri::decode_form_component(key), uri::decode_form_component(value)
+            ) else {
+                return Err(format!("Query does not represent valid UTF-8: {field}"))?;
+            };
+            pairs.push((key, value));
+        }
+
+        Ok(Query { pairs })
+    }
+
+    /// Returns the first value given for `key`.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns all the values given for `key`, in order.
+    pub fn get_all<'q>(&'q self, key: &'q str) -> impl Iterator<Item = &'q str> {
+        self.pairs.iter().filter(move |(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+
+    /// Returns `true` if `key` was given at least once.
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Returns all the key-value pairs, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.pairs.iter().map(|(k, v)| (k.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+
+/// Size limits applied when reading a request body.
+#[derive(Debug, Clone, Copy)]
+pub struct BodyLimits {
+    /// Bodies up to this size (in bytes) are read into memory before the request is
+    /// routed; larger ones are read on demand from the connection.
+    pub buffer_size: usize,
+
+    /// Bodies larger than this size (in bytes) are rejected.
+    pub max_size: usize,
+}
+
+
+/// Errors found while reading a request body.
+#[derive(Debug, Clone)]
+pub enum BodyError {
+    /// The body or its framing headers are invalid (maps to 400 Bad Request).
+    Malformed(String),
+
+    /// The body exceeds the maximum allowed size (maps to 413 Payload Too Large).
+    TooLarge { length: usize, max_size: usize },
+
+    /// The client did not send the body in time (maps to 408 Request Timeout).
+    Timeout,
+}
+
+impl BodyError {
+
+    /// Returns the error for a failed read of the body: a timeout, or else `malformed` (e.g.
+    /// for a body that ends too soon).
+    fn from_io(error: &io::Error, malformed: impl FnOnce() -> String) -> BodyError {
+        match error.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => BodyError::Timeout,
+            _ => BodyError::Malformed(malformed()),
+        }
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::Malformed(msg) => write!(f, "Malformed request body: {msg}"),
+            BodyError::TooLarge { length, max_size } => write!(f,
+                "Request body of {length} bytes exceeds the maximum of {max_size} bytes"
+            ),
+            BodyError::Timeout => write!(f, "Request body timeout"),
+        }
+    }
+}
+
+impl Error for BodyError {}
+
+
+/// HTTP Request Body
+///
+/// Small bodies are buffered in memory, larger ones stay in the connection and are
+/// read on demand with `reader()`.
+pub struct Body<'a> {
+    kind: BodyKind<'a>,
+}
+
+enum BodyKind<'a> {
+    Empty,
+    Buffered { bytes: Vec<u8>, trailers: HeaderMap },
+    Stream(RefCell<BodyStream<'a>>),
+}
+
+/// Source of a body that is read on demand.
+enum BodyStream<'a> {
+    Fixed(io::Take<&'a mut dyn BufRead>),
+    // The already decoded prefix followed by the rest of the chunked stream.
+    Chunked(io::Chain<io::Cursor<Vec<u8>>, ChunkedReader<'a>>),
+}
+
+
+impl<'a> Body<'a> {
+
+    /// A body with no content.
+    pub fn empty() -> Body<'a> {
+        Body { kind: BodyKind::Empty }
+    }
+
+    /// A body with the given in-memory content.
+    pub fn from_bytes(bytes: Vec<u8>) -> Body<'a> {
+        Body { kind: BodyKind::Buffered { bytes, trailers: HeaderMap::new() } }
+    }
+
+    /// Prepares a body of `length` bytes to be read from `stream`: it is read into memory if
+    /// it fits in `limits.buffer_size`, otherwise it is left in `stream` to be read on demand.
+    pub fn read_from(stream: &'a mut dyn BufRead, length: usize, limits: &BodyLimits) ->
+        Result<Body<'a>, BodyError>
+    {
+        if length > limits.max_size {
+            return Err(BodyError::TooLarge { length, max_size: limits.max_size });
+        }
+
+        if length == 0 {
+            Ok(Body::empty())
+        }
+        else if length <= limits.buffer_size {
+            let mut bytes = vec![0; length];
+            stream.read_exact(&mut bytes).map_err(|e| BodyError::from_io(&e, ||
+                format!("Incomplete body, expected {length} bytes: {e}")
+            ))?;
+            Ok(Body::from_bytes(bytes))
+        }
+        else {
+            let stream = BodyStream::Fixed(stream.take(length as u64));
+            Ok(Body { kind: BodyKind::Stream(RefCell::new(stream)) })
+        }
+    }
+
+    /// Prepares a body in chunked transfer coding to be read from `stream`. Up to
+    /// `limits.buffer_size` bytes are decoded right away: if the whole body fits, it is kept
+    /// in memory, otherwise the rest is decoded on demand.
+    pub fn read_chunked(stream: &'a mut dyn BufRead, limits: &BodyLimits) ->
+        Result<Body<'a>, BodyError>
+    {
+        let mut decoder = ChunkedReader::new(stream, limits.max_size);
+        let mut prefix = Vec::new();
+
+        (&mut decoder).take(limits.buffer_size as u64 + 1).read_to_end(&mut prefix).map_err(
+            |e| decoder.error().unwrap_or_else(|| BodyError::from_io(&e, || e.to_string()))
+        )?;
+
+        if decoder.is_done() {
+            let trailers = decoder.trailers;
+            if prefix.is_empty() && trailers.is_empty() {
+                Ok(Body::empty())
+            } else {
+                Ok(Body { kind: BodyKind::Buffered { bytes: prefix, trailers } })
+            }
+        }
+        else {
+            let stream = BodyStream::Chunked(io::Cursor::new(prefix).chain(decoder));
+            Ok(Body { kind: BodyKind::Stream(RefCell::new(stream)) })
+        }
+    }
+
+    /// Returns `true` if the request has no body.
+    pub fn is_empty(&self) -> bool {
+        matches!(self.kind, BodyKind::Empty)
+    }
+
+    /// Returns `true` once all the body has been read from the connection.
+    pub fn is_complete(&self) -> bool {
+        match &self.kind {
+            BodyKind::Stream(stream) => match &*stream.borrow() {
+                BodyStream::Fixed(stream) => stream.limit() == 0,
+                BodyStream::Chunked(stream) => stream.get_ref().1.is_done(),
+            },
+            _ => true,
+        }
+    }
+
+    /// Returns the body content if it was buffered in memory, `None` if it must be read
+    /// with `reader()`.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match &self.kind {
+            BodyKind::Empty => Some(&[]),
+            BodyKind::Buffered { bytes, .. } => Some(bytes),
+            BodyKind::Stream(_) => None,
+        }
+    }
+
+    /// Returns a reader for the body content. Buffered bodies are read from the start on
+    /// each call, while streamed bodies continue where the previous reader left off.
+    ///
+    /// # Panics
+    /// When called for a streamed body while a previously returned reader is still alive.
+    ///
+    pub fn reader(&self) -> Box<dyn Read + '_> {
+        match &self.kind {
+            BodyKind::Empty => Box::new(io::empty()),
+            BodyKind::Buffered { bytes, .. } => Box::new(&bytes[..]),
+            BodyKind::Stream(stream) => Box::new(StreamReader(stream.borrow_mut())),
+        }
+    }
+
+    /// Returns the trailer fields sent after a chunked body, or `None` if the body has not
+    /// been completely read yet. Non-chunked bodies have no trailers.
+    pub fn trailers(&self) -> Option<HeaderMap> {
+        match &self.kind {
+            BodyKind::Empty => Some(HeaderMap::new()),
+            BodyKind::Buffered { trailers, .. } => Some(trailers.clone()),
+            BodyKind::Stream(stream) => match &*stream.borrow() {
+                BodyStream::Fixed(stream) => (stream.limit() == 0).then(HeaderMap::new),
+                BodyStream::Chunked(stream) => {
+                    let decoder = stream.get_ref().1;
+                    decoder.is_done().then(|| decoder.trailers.clone())
+                },
+            },
+        }
+    }
+
+    /// Returns the error found while decoding a streamed body, if any.
+    pub fn error(&self) -> Option<BodyError> {
+        match &self.kind {
+            BodyKind::Stream(stream) => match &*stream.borrow() {
+                BodyStream::Chunked(stream) => stream.get_ref().1.error(),
+                BodyStream::Fixed(_) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            BodyKind::Empty => write!(f, "Body::Empty"),
+            BodyKind::Buffered { bytes, .. } =>
+                write!(f, "Body::Buffered({} bytes)", bytes.len()),
+            BodyKind::Stream(stream) => match stream.try_borrow().as_deref() {
+                Ok(BodyStream::Fixed(stream)) =>
+                    write!(f, "Body::Stream({} bytes left)", stream.limit()),
+                Ok(BodyStream::Chunked(_)) => write!(f, "Body::Stream(chunked)"),
+                Err(_) => write!(f, "Body::Stream(<being read>)"),
+            },
+        }
+    }
+}
+
+
+/// Borrowed access to a streamed body.
+struct StreamReader<'r, 'a>(std::cell::RefMut<'r, BodyStream<'a>>);
+
+impl Read for StreamReader<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut *self.0 {
+            BodyStream::Fixed(stream) => stream.read(buf),
+            BodyStream::Chunked(stream) => stream.read(buf),
+        }
+    }
+}
+
+
+/// Maximum length of a chunk-size or trailer line in a chunked body.
+const CHUNK_LINE_MAX_LEN : usize = 4096;
+
+/// Decoder for the chunked transfer coding: reads the chunks from the source stream and
+/// yields their data. Trailer fields found after the last chunk are kept in `trailers`.
+pub struct ChunkedReader<'a> {
+    source:     &'a mut dyn BufRead,
+    state:      ChunkState,
+    total:      usize,
+    max_size:   usize,
+    trailers:   HeaderMap,
+    error:      Option<BodyError>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ChunkState {
+    /// Expecting a chunk-size line.
+    Size,
+    /// Inside chunk data, with the given number of bytes left.
+    Data(u64),
+    /// Expecting the line break that follows chunk data.
+    DataEnd,
+    /// Last chunk and trailers read.
+    Done,
+}
+
+
+impl<'a> ChunkedReader<'a> {
+
+    /// Creates a decoder reading from `source` that fails once the decoded data exceeds
+    /// `max_size` bytes.
+    pub fn new(source: &'a mut dyn BufRead, max_size: usize) -> ChunkedReader<'a> {
+        ChunkedReader {
+            source,
+            state: ChunkState::Size,
+            total: 0,
+            max_size,
+            trailers: HeaderMap::new(),
+            error: None,
+        }
+    }
+
+    /// Returns `true` once the last chunk and the trailers have been read.
+    pub fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    /// Returns the error that stopped the decoding, if any.
+    pub fn error(&self) -> Option<BodyError> {
+        self.error.clone()
+    }
+
+    /// Returns the trailer fields (complete only once `is_done()`).
+    pub fn trailers(&self) -> &HeaderMap {
+        &self.trailers
+    }
+
+    /// Records `error` so that it is reported on this and any further read.
+    fn fail(&mut self, error: BodyError) -> io::Error {
+        let io_error = io::Error::new(io::ErrorKind::InvalidData, error.clone());
+        self.error = Some(error);
+        io_error
+    }
+
+    /// Reads a line terminated by LF (optionally preceded by CR), without the terminator.
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = Vec::new();
+        (&mut *self.source).take(CHUNK_LINE_MAX_LEN as u64 + 1).read_until(b'\n', &mut line)?;
+
+        if line.pop() != Some(b'\n') {
+            return Err(self.fail(BodyError::Malformed(if line.len() > CHUNK_LINE_MAX_LEN {
+                "Chunk line too long".into()
+            } else {
+                "Unexpected end of chunked body".into()
+            })));
+        }
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(
+            |_| self.fail(BodyError::Malformed("Invalid characters in chunk line".into()))
+        )
+    }
+
+    /// Reads a chunk-size line, ignoring chunk extensions.
+    fn read_chunk_size(&mut self) -> io::Result<u64> {
+        let line = self.read_line()?;
+        let size_str = line.split(';').next().unwrap_or("").trim_end_matches([' ', '\t']);
+
+        let is_valid = !size_str.is_empty() && size_str.len() <= 16
+            && size_str.bytes().all(|b| b.is_ascii_hexdigit());
+
+        match is_valid.then(|| u64::from_str_radix(size_str, 16)) {
+            Some(Ok(size)) => Ok(size),
+            _ => Err(self.fail(BodyError::Malformed(format!("Invalid chunk size: '{line}'")))),
+        }
+    }
+
+    /// Reads the trailer section that follows the last chunk, up to the empty line.
+    fn read_trailers(&mut self) -> io::Result<()> {
+        loop {
+            let line = self.read_line()?;
+            if line.is_empty() {
+                return Ok(());
+            }
+            // Invalid trailer lines are ignored, as are trailers in general by most servers.
+            if let Some((name, value)) = line.split_once(':') {
+                self.trailers.append(name.trim(), value.trim());
+            }
+        }
+    }
+}
+
+
+impl Read for ChunkedReader<'_> {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(error) = &self.error {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, error.clone()));
+        }
+
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let size = self.read_chunk_size()?;
+                    if size == 0 {
+                        self.read_trailers()?;
+                        self.state = ChunkState::Done;
+                        continue;
+                    }
+                    let total = usize::try_from(size).ok()
+                        .and_then(|size| size.checked_add(self.total))
+                        .filter(|total| *total <= self.max_size);
+                    let Some(total) = total else {
+                        return Err(self.fail(BodyError::TooLarge {
+                            length: self.total.saturating_add(size as usize),
+                            max_size: self.max_size,
+                        }));
+                    };
+                    self.total = total;
+                    self.state = ChunkState::Data(size);
+                },
+
+                ChunkState::Data(left) => {
+                    if buf.is_empty() {
+                        return Ok(0);
+                    }
+                    let max_len = buf.len().min(usize::try_from(left).unwrap_or(usize::MAX));
+                    let len = self.source.read(&mut buf[..max_len])?;
+                    if len == 0 {
+                        return Err(self.fail(BodyError::Malformed(
+                            "Unexpected end of chunk data".into()
+                        )));
+                    }
+                    let left = left - len as u64;
+                    self.state = if left == 0 { ChunkState::DataEnd } else { ChunkState::Data(left) };
+                    return Ok(len);
+                },
+
+                ChunkState::DataEnd => {
+                    if !self.read_line()?.is_empty() {
+                        return Err(self.fail(BodyError::Malformed(
+                            "Missing line break after chunk data".into()
+                        )));
+                    }
+                    self.state = ChunkState::Size;
+                },
+
+                ChunkState::Done => return Ok(0),
+            }
+        }
+    }
+}
+
+
+/// Reads a request header from `stream`, up to and including the empty line that ends it.
+/// Empty lines before the request line are skipped.
+fn retrieve_header(stream: &mut dyn BufRead) -> Result<String, ServerError> {
+    let mut header = Vec::new();
+
+    loop {
+        let len_left = (HTTP_HEADER_MAX_LEN - header.len()) as u64;
+        let line_start = header.len();
+        let len = stream.take(len_left).read_until(b'\n', &mut header)?;
+
+        let line = &header[line_start..];
+        if line.last() != Some(&b'\n') {
+            return Err(if len == 0 && header.len() < HTTP_HEADER_MAX_LEN {
+                ServerError::Parse("Connection closed before the end of the header".into())
+            } else {
+                ServerError::HeaderTooLarge { max_size: HTTP_HEADER_MAX_LEN }
+            });
+        }
+
+        if line == b"\r\n" || line == b"\n" {
+            if line_start == 0 {
+                header.clear(); // Before the request line
+                continue;
+            }
+            header.truncate(line_start);
+            return Ok(String::from_utf8_lossy(&header).to_string());
+        }
+    }
+}
+
+
+/// Buffered reader for the requests that arrive on a connection.
+///
+/// Bytes read past the end of a request are kept for the next one, so pipelined requests
+/// (sent back-to-back without waiting for responses) are read in order. Answering each
+/// request before reading the next one keeps the responses in the same order.
+pub struct ConnectionReader<R: Read> {
+    reader: BufReader<R>,
+}
+
+impl<R: Read> ConnectionReader<R> {
+
+    pub fn new(source: R) -> ConnectionReader<R> {
+        ConnectionReader { reader: BufReader::new(source) }
+    }
+
+    /// Waits until the next request starts arriving. Returns `false` if the connection was
+    /// closed instead.
+    pub fn wait_for_request(&mut self) -> io::Result<bool> {
+        Ok(!self.reader.fill_buf()?.is_empty())
+    }
+
+    /// Reads the next request, which borrows the connection until it is dropped (to read
+    /// the body on demand). See `Request::parse_from_stream()`.
+    pub fn next_request(&mut self, limits: &BodyLimits) -> Result<Request<'_>, ServerError> {
+        Request::parse_from_stream(&mut self.reader, limits)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: BodyLimits = BodyLimits { buffer_size: 8, max_size: 16 };
+
+    #[test]
+    fn test_body_buffered() {
+        let mut stream: &[u8] = b"hello world";
+        let body = Body::read_from(&mut stream, 5, &LIMITS).unwrap();
+        assert_eq!(body.as_bytes(), Some(&b"hello"[..]));
+        assert_eq!(stream, b" world");
+    }
+
+    #[test]
+    fn test_body_streamed() {
+        let mut stream: &[u8] = b"0123456789abcdefXYZ";
+        let body = Body::read_from(&mut stream, 12, &LIMITS).unwrap();
+        assert_eq!(body.as_bytes(), None);
+
+        let mut content = String::new();
+        body.reader().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "0123456789ab");
+    }
+
+    #[test]
+    fn test_body_errors() {
+        let mut stream: &[u8] = b"short";
+        assert!(matches!(
+            Body::read_from(&mut stream, 17, &LIMITS),
+            Err(BodyError::TooLarge { length: 17, max_size: 16 })
+        ));
+        assert!(matches!(
+            Body::read_from(&mut stream, 6, &LIMITS),
+            Err(BodyError::Malformed(_))
+        ));
+
+        /// A connection whose client stopped sending before the read timeout.
+        struct Stalled;
+        impl Read for Stalled {
+            fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+        let mut stream = BufReader::new((&b"short"[..]).chain(Stalled));
+        assert!(matches!(Body::read_from(&mut stream, 6, &LIMITS), Err(BodyError::Timeout)));
+        let mut stream = BufReader::new((&b"4\r\nWi"[..]).chain(Stalled));
+        assert!(matches!(Body::read_chunked(&mut stream, &LIMITS), Err(BodyError::Timeout)));
+    }
+
+    #[test]
+    fn test_body_chunked() {
+        let mut stream: &[u8] = b"4\r\nWiki\r\n7;ext=1\r\npedia i\r\nB\r\nn \r\nchunks.\r\n\
+            0\r\nExpires: never\r\n\r\nNEXT";
+        let limits = BodyLimits { buffer_size: 64, max_size: 64 };
+        let body = Body::read_chunked(&mut stream, &limits).unwrap();
+
+        assert_eq!(body.as_bytes(), Some(&b"Wikipedia in \r\nchunks."[..]));
+        assert_eq!(body.trailers().unwrap().get("expires"), Some("never"));
+        assert_eq!(stream, b"NEXT");
+    }
+
+    #[test]
+    fn test_body_chunked_streamed() {
+        let mut stream: &[u8] = b"6\r\n012345\r\n6\r\n6789ab\r\n0\r\n\r\n";
+        let body = Body::read_chunked(&mut stream, &LIMITS).unwrap();
+        assert_eq!(body.as_bytes(), None);
+        assert_eq!(body.trailers(), None);
+
+        let mut content = String::new();
+        body.reader().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "0123456789ab");
+        assert_eq!(body.trailers(), Some(HeaderMap::new()));
+    }
+
+    #[test]
+    fn test_body_chunked_errors() {
+        for bad_chunks in [&b"x\r\nab\r\n0\r\n\r\n"[..], b"-1\r\n", b"2\r\nabc\r\n0\r\n\r\n",
+                           b"2\r\nab", b"\r\n", b"11111111111111111\r\n"]
+        {
+            let mut stream = bad_chunks;
+            assert!(
+                matches!(Body::read_chunked(&mut stream, &LIMITS), Err(BodyError::Malformed(_))),
+                "accepted {:?}", String::from_utf8_lossy(bad_chunks)
+            );
+        }
+
+        // Too large while buffering:
+        let chunks = b"9\r\n012345678\r\n9\r\n012345678\r\n0\r\n\r\n";
+        let limits = BodyLimits { buffer_size: 64, max_size: 16 };
+        assert!(matches!(
+            Body::read_chunked(&mut &chunks[..], &limits),
+            Err(BodyError::TooLarge { length: 18, max_size: 16 })
+        ));
+
+        // Too large while streaming:
+        let mut stream = &chunks[..];
+        let body = Body::read_chunked(&mut stream, &LIMITS).unwrap();
+        assert!(body.reader().read_to_end(&mut Vec::new()).is_err());
+        assert!(matches!(body.error(), Some(BodyError::TooLarge { .. })));
+    }
+
+    #[test]
+    fn test_body_framing() {
+        let request = Request::parse(
+            "PUT / HTTP/1.1\r\nTransfer-Encoding: Chunked").unwrap();
+        assert!(matches!(request.body_framing(), Ok(BodyFraming::Chunked)));
+
+        for bad_header in [
+            "Transfer-Encoding: gzip, chunked",
+            "Transfer-Encoding: chunked\r\nContent-Length: 3",
+        ] {
+            let request = Request::parse(&format!("PUT / HTTP/1.1\r\n{bad_header}")).unwrap();
+            assert!(request.body_framing().is_err(), "accepted '{bad_header}'");
+        }
+
+        // Fields that other parsers may read differently are rejected with the request:
+        for bad_header in [
+            "Transfer-Encoding : chunked",
+            " Transfer-Encoding: chunked",
+            "X-Other: value\r\n Transfer-Encoding: chunked",
+            "X-Other: value\r\n\tfolded",
+            "Content Length: 3",
+        ] {
+            let request = Request::parse(&format!("PUT / HTTP/1.1\r\n{bad_header}"));
+            assert!(request.is_err(), "accepted '{bad_header}'");
+        }
+    }
+
+    #[test]
+    fn test_method() {
+        let request = Request::parse("delete /items/1 HTTP/1.1").unwrap();
+        assert_eq!(request.method, Method::Delete);
+        assert_eq!(request.path, "/items/1");
+
+        let request = Request::parse("PROPFIND /dav HTTP/1.1").unwrap();
+        assert_eq!(request.method, Method::Extension("PROPFIND".into()));
+        assert_eq!(request.method.to_string(), "PROPFIND");
+
+        assert!(Request::parse("G(E)T / HTTP/1.1").is_err());
+    }
+
+    #[test]
+    fn test_query() {
+        let request = Request::parse(
+            "GET /search%3Fx/a%20b?q=rust+http&tag=a%26b&tag=c%3Dd&flag&&=v HTTP/1.1").unwrap();
+        assert_eq!(request.path, "/search?x/a b");
+        assert_eq!(request.query.get("q"), Some("rust http"));
+        assert_eq!(request.query.get_all("tag").collect::<Vec<_>>(), ["a&b", "c=d"]);
+        assert_eq!(request.query.get("flag"), Some(""));
+        assert_eq!(request.query.get(""), Some("v"));
+        assert_eq!(request.query.len(), 5);
+        assert!(!request.query.contains_key("none"));
+
+        let request = Request::parse("GET /info HTTP/1.1").unwrap();
+        assert_eq!(request.path, "/info");
+        assert!(request.query.is_empty());
+    }
+
+    #[test]
+    fn test_keep_alive() {
+        for (header, keep_alive) in [
+            ("GET / HTTP/1.1", true),
+            ("GET / HTTP/1.1\r\nConnection: Close", false),
+            ("GET / HTTP/1.0", false),
+            ("GET / HTTP/1.0\r\nConnection: Keep-Alive", true),
+        ] {
+            assert_eq!(Request::parse(header).unwrap().keep_alive(), keep_alive, "{header}");
+        }
+    }
+
+    #[test]
+    fn test_pipelined_requests() {
+        let stream: &[u8] = b"PUT /a HTTP/1.1\r\nContent-Length: 3\r\n\r\nabc\
+            \r\nPOST /b HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\nd\r\n0\r\n\r\n\
+            GET /c HTTP/1.1\nHost: x\n\nGET /d HTTP/1.1\r\n";
+        let mut connection = ConnectionReader::new(stream);
+        let limits = BodyLimits { buffer_size: 8, max_size: 8 };
+
+        let mut next_request = || {
+            assert!(connection.wait_for_request().unwrap());
+            let request = connection.next_request(&limits)?;
+            let body = request.body.as_bytes().unwrap().to_vec();
+            Ok::<_, Box<dyn Error>>((request.path, body))
+        };
+
+        assert_eq!(next_request().unwrap(), ("/a".into(), b"abc".to_vec()));
+        assert_eq!(next_request().unwrap(), ("/b".into(), b"d".to_vec()));
+        assert_eq!(next_request().unwrap(), ("/c".into(), vec![]));
+        assert!(next_request().is_err()); // Incomplete header
+        assert!(!connection.wait_for_request().unwrap());
+    }
+
+    #[test]
+    fn test_header_too_long() {
+        let header = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "x".repeat(HTTP_HEADER_MAX_LEN));
+        let mut connection = ConnectionReader::new(header.as_bytes());
+        let limits = BodyLimits { buffer_size: 8, max_size: 8 };
+        assert!(matches!(
+            connection.next_request(&limits),
+            Err(ServerError::HeaderTooLarge { max_size: HTTP_HEADER_MAX_LEN })
+        ));
+    }
+
+    #[test]
+    fn test_content_length() {
+        let request = Request::parse("PUT / HTTP/1.1\r\ncontent-length: 42").unwrap();
+        assert_eq!(request.content_length().unwrap(), Some(42));
+
+        let request = Request::parse(
+            "PUT / HTTP/1.1\r\nContent-Length: 42\r\nContent-Length: 42").unwrap();
+        assert_eq!(request.content_length().unwrap(), Some(42));
+
+        for bad_value in ["-1", "4 2", "0x10", "", "1,1", "1\r\nContent-Length: 2"] {
+            let header = format!("PUT / HTTP/1.1\r\nContent-Length: {bad_value}");
+            let request = Request::parse(&header).unwrap();
+            assert!(request.content_length().is_err(), "accepted '{bad_value}'");
+        }
+    }
+}
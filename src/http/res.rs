@@ -0,0 +1,908 @@
+use std::path::{Path, PathBuf};
+use std::fs;
+use std::fmt;
+use std::io::{self, Read, Seek, Write};
+use log::{error, trace};
+use super::{HeaderMap, InvalidHeader, Version};
+use super::date::DateTime;
+use crate::cache::{CacheDefaults, CachePolicy};
+use crate::compression;
+use crate::mime::{self, MimeTypes};
+
+/// HTTP Response Status
+///
+/// Covers the status codes in the IANA registry; any other code (or a non-standard reason
+/// phrase) can be given with `Custom`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    // 1xx Informational
+    Continue,
+    SwitchingProtocols,
+    Processing,
+    EarlyHints,
+
+    // 2xx Successful
+    OK,
+    Created,
+    Accepted,
+    NonAuthoritativeInformation,
+    NoContent,
+    ResetContent,
+    PartialContent,
+    MultiStatus,
+    AlreadyReported,
+    ImUsed,
+
+    // 3xx Redirection
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    UseProxy,
+    TemporaryRedirect,
+    PermanentRedirect,
+
+    // 4xx Client Error
+    BadRequest,
+    Unauthorized,
+    PaymentRequired,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    NotAcceptable,
+    ProxyAuthenticationRequired,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    MisdirectedRequest,
+    UnprocessableContent,
+    Locked,
+    FailedDependency,
+    TooEarly,
+    UpgradeRequired,
+    PreconditionRequired,
+    TooManyRequests,
+    RequestHeaderFieldsTooLarge,
+    UnavailableForLegalReasons,
+
+    // 5xx Server Error
+    InternalError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+    VariantAlsoNegotiates,
+    InsufficientStorage,
+    LoopDetected,
+    NotExtended,
+    NetworkAuthenticationRequired,
+
+    /// Any status code with its own reason phrase.
+    Custom(u16, String),
+}
+
+impl Status {
+
+    /// Returns the numeric status code.
+    pub fn code(&self) -> u16 {
+        use Status::*;
+        match self {
+            Continue                      => 100,
+            SwitchingProtocols            => 101,
+            Processing                    => 102,
+            EarlyHints                    => 103,
+            OK                            => 200,
+            Created                       => 201,
+            Accepted                      => 202,
+            NonAuthoritativeInformation   => 203,
+            NoContent                     => 204,
+            ResetContent                  => 205,
+            PartialContent                => 206,
+            MultiStatus                   => 207,
+            AlreadyReported               => 208,
+            ImUsed                        => 226,
+            MultipleChoices               => 300,
+            MovedPermanently              => 301,
+            Found                         => 302,
+            SeeOther                      => 303,
+            NotModified                   => 304,
+            UseProxy                      => 305,
+            TemporaryRedirect             => 307,
+            PermanentRedirect             => 308,
+            BadRequest                    => 400,
+            Unauthorized                  => 401,
+            PaymentRequired               => 402,
+            Forbidden                     => 403,
+            NotFound                      => 404,
+            MethodNotAllowed              => 405,
+            NotAcceptable                 => 406,
+            ProxyAuthenticationRequired   => 407,
+            RequestTimeout                => 408,
+            Conflict                      => 409,
+            Gone                          => 410,
+            LengthRequired                => 411,
+            PreconditionFailed            => 412,
+            PayloadTooLarge               => 413,
+            UriTooLong                    => 414,
+            UnsupportedMediaType          => 415,
+            RangeNotSatisfiable           => 416,
+            ExpectationFailed             => 417,
+            MisdirectedRequest            => 421,
+            UnprocessableContent          => 422,
+            Locked                        => 423,
+            FailedDependency              => 424,
+            TooEarly                      => 425,
+            UpgradeRequired               => 426,
+            PreconditionRequired          => 428,
+            TooManyRequests               => 429,
+            RequestHeaderFieldsTooLarge   => 431,
+            UnavailableForLegalReasons    => 451,
+            InternalError                 => 500,
+            NotImplemented                => 501,
+            BadGateway                    => 502,
+            ServiceUnavailable            => 503,
+            GatewayTimeout                => 504,
+            HttpVersionNotSupported       => 505,
+            VariantAlsoNegotiates         => 506,
+            InsufficientStorage           => 507,
+            LoopDetected                  => 508,
+            NotExtended                   => 510,
+            NetworkAuthenticationRequired => 511,
+            Custom(code, _) => *code,
+        }
+    }
+
+    /// Returns the reason phrase sent along the status code.
+    pub fn reason(&self) -> &str {
+        use Status::*;
+        match self {
+            Continue                      => "Continue",
+            SwitchingProtocols            => "Switching Protocols",
+            Processing                    => "Processing",
+            EarlyHints                    => "Early Hints",
+            OK                            => "OK",
+            Created                       => "Created",
+            Accepted                      => "Accepted",
+            NonAuthoritativeInformation   => "Non-Authoritative Information",
+            NoContent                     => "No Content",
+            ResetContent                  => "Reset Content",
+            PartialContent                => "Partial Content",
+            MultiStatus                   => "Multi-Status",
+            AlreadyReported               => "Already Reported",
+            ImUsed                        => "IM Used",
+            MultipleChoices               => "Multiple Choices",
+            MovedPermanently              => "Moved Permanently",
+            Found                         => "Found",
+            SeeOther                      => "See Other",
+            NotModified                   => "Not Modified",
+            UseProxy                      => "Use Proxy",
+            TemporaryRedirect             => "Temporary Redirect",
+            PermanentRedirect             => "Permanent Redirect",
+            BadRequest                    => "Bad Request",
+            Unauthorized                  => "Unauthorized",
+            PaymentRequired               => "Payment Required",
+            Forbidden                     => "Forbidden",
+            NotFound                      => "Not Found",
+            MethodNotAllowed              => "Method Not Allowed",
+            NotAcceptable                 => "Not Acceptable",
+            ProxyAuthenticationRequired   => "Proxy Authentication Required",
+            RequestTimeout                => "Request Timeout",
+            Conflict                      => "Conflict",
+            Gone                          => "Gone",
+            LengthRequired                => "Length Required",
+            PreconditionFailed            => "Precondition Failed",
+            PayloadTooLarge               => "Content Too Large",
+            UriTooLong                    => "URI Too Long",
+            UnsupportedMediaType          => "Unsupported Media Type",
+            RangeNotSatisfiable           => "Range Not Satisfiable",
+            ExpectationFailed             => "Expectation Failed",
+            MisdirectedRequest            => "Misdirected Request",
+            UnprocessableContent          => "Unprocessable Content",
+            Locked                        => "Locked",
+            FailedDependency              => "Failed Dependency",
+            TooEarly                      => "Too Early",
+            UpgradeRequired               => "Upgrade Required",
+            PreconditionRequired          => "Precondition Required",
+            TooManyRequests               => "Too Many Requests",
+            RequestHeaderFieldsTooLarge   => "Request Header Fields Too Large",
+            UnavailableForLegalReasons    => "Unavailable For Legal Reasons",
+            InternalError                 => "Internal Server Error",
+            NotImplemented                => "Not Implemented",
+            BadGateway                    => "Bad Gateway",
+            ServiceUnavailable            => "Service Unavailable",
+            GatewayTimeout                => "Gateway Timeout",
+            HttpVersionNotSupported       => "HTTP Version Not Supported",
+            VariantAlsoNegotiates         => "Variant Also Negotiates",
+            InsufficientStorage           => "Insufficient Storage",
+            LoopDetected                  => "Loop Detected",
+            NotExtended                   => "Not Extended",
+            NetworkAuthenticationRequired => "Network Authentication Required",
+            Custom(_, reason) => reason,
+        }
+    }
+
+    /// Returns the status for `code`, as a `Custom` status without reason phrase if it is
+    /// not a registered one.
+    pub fn from_code(code: u16) -> Status {
+        use Status::*;
+        match code {
+            100 => Continue,
+            101 => SwitchingProtocols,
+            102 => Processing,
+            103 => EarlyHints,
+            200 => OK,
+            201 => Created,
+            202 => Accepted,
+            203 => NonAuthoritativeInformation,
+            204 => NoContent,
+            205 => ResetContent,
+            206 => PartialContent,
+            207 => MultiStatus,
+            208 => AlreadyReported,
+            226 => ImUsed,
+            300 => MultipleChoices,
+            301 => MovedPermanently,
+            302 => Found,
+            303 => SeeOther,
+            304 => NotModified,
+            305 => UseProxy,
+            307 => TemporaryRedirect,
+            308 => PermanentRedirect,
+            400 => BadRequest,
+            401 => Unauthorized,
+            402 => PaymentRequired,
+            403 => Forbidden,
+            404 => NotFound,
+            405 => MethodNotAllowed,
+            406 => NotAcceptable,
+            407 => ProxyAuthenticationRequired,
+            408 => RequestTimeout,
+            409 => Conflict,
+            410 => Gone,
+            411 => LengthRequired,
+            412 => PreconditionFailed,
+            413 => PayloadTooLarge,
+            414 => UriTooLong,
+            415 => UnsupportedMediaType,
+            416 => RangeNotSatisfiable,
+            417 => ExpectationFailed,
+            421 => MisdirectedRequest,
+            422 => UnprocessableContent,
+            423 => Locked,
+            424 => FailedDependency,
+            425 => TooEarly,
+            426 => UpgradeRequired,
+            428 => PreconditionRequired,
+            429 => TooManyRequests,
+            431 => RequestHeaderFieldsTooLarge,
+            451 => UnavailableForLegalReasons,
+            500 => InternalError,
+            501 => NotImplemented,
+            502 => BadGateway,
+            503 => ServiceUnavailable,
+            504 => GatewayTimeout,
+            505 => HttpVersionNotSupported,
+            506 => VariantAlsoNegotiates,
+            507 => InsufficientStorage,
+            508 => LoopDetected,
+            510 => NotExtended,
+            511 => NetworkAuthenticationRequired,
+            _ => Custom(code, String::new()),
+        }
+    }
+
+    /// Returns the status as it can be written in a status line: `Custom` codes outside
+    /// 100-999 become `500 Internal Server Error`, and reason phrases with control characters
+    /// (like CR LF, which would inject header fields) are dropped.
+    fn to_sendable(&self) -> Status {
+        match self {
+            Status::Custom(code, _) if !(100..=999).contains(code) => {
+                error!("Invalid status code {code}, sent as 500");
+                Status::InternalError
+            },
+            Status::Custom(code, reason) if super::has_control_chars(reason) => {
+                error!("Invalid reason phrase for status {code}, not sent");
+                Status::Custom(*code, String::new())
+            },
+            status => status.clone(),
+        }
+    }
+
+    /// Returns `true` for 1xx codes.
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+
+    /// Returns `true` for 2xx codes.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    /// Returns `true` for 3xx codes.
+    pub fn is_redirection(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    /// Returns `true` for 4xx codes.
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    /// Returns `true` for 5xx codes.
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.code(), self.reason())
+    }
+}
+
+
+/// The actual HTTP response data to send
+pub struct TextResponse {
+    pub status: Status,
+    pub headers: HeaderMap,
+    pub body: Body,
+}
+
+/// Body of a `TextResponse`
+pub enum Body {
+    Bytes(Vec<u8>),
+    /// Content read (and sent) incrementally; when `length` is unknown it is sent with
+    /// chunked transfer coding.
+    Stream { reader: Box<dyn Read + Send>, length: Option<u64> },
+    /// The `length` bytes of a file from its current position, which can be sent in parts
+    /// (see `range`).
+    File { file: fs::File, length: u64 },
+    /// Content that is described in the header (with its length, when known) but not sent,
+    /// as for responses to `HEAD` requests.
+    Omitted { length: Option<u64> },
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Body {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Body {
+        Body::Bytes(text.into())
+    }
+}
+
+
+/// Size of the buffer used to copy streamed content, which is also the maximum chunk size.
+const STREAM_CHUNK_LEN : usize = 16 * 1024;
+
+
+impl TextResponse {
+
+    /// Creates a response with no header fields (other than those framing the body).
+    pub fn new(status: Status, body: Body) -> TextResponse {
+        TextResponse { status, headers: HeaderMap::new(), body }
+    }
+
+    /// Creates a response with the headers given by the application.
+    fn with_app_headers(status: Status, app_headers: HeaderMap, body: Body) -> TextResponse {
+        TextResponse { status, headers: app_headers, body }
+    }
+
+    /// Drops the body, keeping the headers it would have been sent with (for `HEAD` requests).
+    /// Streamed content is never read.
+    pub fn without_body(self) -> TextResponse {
+        let length = match &self.body {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Stream { length, .. } | Body::Omitted { length } => *length,
+            Body::File { length, .. } => Some(*length),
+        };
+        TextResponse { body: Body::Omitted { length }, ..self }
+    }
+
+    /// Serializes the response for a `version` client into `writer`. Streamed content is
+    /// copied in pieces, so it is never held in memory as a whole.
+    pub fn write_to(self, version: Version, writer: &mut dyn Write) -> io::Result<()> {
+        // `Custom` statuses are public, so they may be invalid:
+        let status = self.status.to_sendable();
+        let mut head = format!("{version} {status}\r\n");
+        for (name, value) in self.headers.iter() {
+            // The message framing is determined here, not by the application:
+            if name.eq_ignore_ascii_case("Content-Length")
+                || name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                continue;
+            }
+            // `headers` is public, so it may have skipped validation:
+            if let Err(e) = super::validate_header(name, value) {
+                error!("Header not sent: {e}");
+                continue;
+            }
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+
+        // These statuses never have content, so it is neither framed nor sent:
+        if status.is_informational()
+            || matches!(status, Status::NoContent | Status::NotModified)
+        {
+            head.push_str("\r\n");
+            writer.write_all(head.as_bytes())?;
+            return writer.flush();
+        }
+
+        match &self.body {
+            Body::Bytes(bytes) =>
+                head.push_str(&format!("Content-Length: {}\r\n", bytes.len())),
+            Body::File { length, .. } =>
+                head.push_str(&format!("Content-Length: {length}\r\n")),
+            Body::Stream { length: Some(length), .. } | Body::Omitted { length: Some(length) } =>
+                head.push_str(&format!("Content-Length: {length}\r\n")),
+            Body::Omitted { length: None } => {},
+            // HTTP/1.0 has no chunked coding: the end of the connection ends the content.
+            Body::Stream { length: None, .. } if version == Version::Http10 => {},
+            Body::Stream { length: None, .. } =>
+                head.push_str("Transfer-Encoding: chunked\r\n"),
+        }
+        head.push_str("\r\n");
+
+        trace!("Response header: {:#?}", head);
+        writer.write_all(head.as_bytes())?;
+
+        match self.body {
+            Body::Bytes(bytes) => writer.write_all(&bytes)?,
+
+            Body::Omitted { .. } => {},
+
+            Body::Stream { reader, length: Some(length) } => copy_exactly(reader, length, writer)?,
+
+            Body::File { file, length } => copy_exactly(file, length, writer)?,
+
+            Body::Stream { mut reader, length: None } if version == Version::Http10 => {
+                io::copy(&mut reader, writer)?;
+            },
+
+            Body::Stream { mut reader, length: None } => {
+                let mut buf = vec![0; STREAM_CHUNK_LEN];
+                loop {
+                    let len = match reader.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(len) => len,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    };
+                    write!(writer, "{len:X}\r\n")?;
+                    writer.write_all(&buf[..len])?;
+                    writer.write_all(b"\r\n")?;
+                }
+                writer.write_all(b"0\r\n\r\n")?;
+            },
+        }
+
+        writer.flush()
+    }
+}
+
+
+/// Copies `length` bytes from `reader` into `writer`, failing if `reader` ends before.
+fn copy_exactly(reader: impl Read, length: u64, writer: &mut dyn Write) -> io::Result<()> {
+    let copied = io::copy(&mut reader.take(length), writer)?;
+    if copied < length {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!(
+            "Response stream ended after {copied} of {length} bytes"
+        )));
+    }
+    Ok(())
+}
+
+
+/// HTTP Response Content
+pub enum Content {
+    /// A file of the server resources, by its path relative to their directory. It is sent
+    /// precompressed when it has a sibling the client accepts (see `Response::resolve()`).
+    ServerFile(PathBuf),
+    /// Any other file, by its absolute path. It is always sent as it is.
+    UserFile(PathBuf),
+    Bytes(Vec<u8>),
+    /// Content produced incrementally, e.g. a large file or the output of a process. If the
+    /// response has a `Content-Length` header it is used as the stream length, otherwise the
+    /// content is sent in chunks.
+    Stream(Box<dyn Read + Send>),
+    UnknownRoute,
+}
+
+impl Content {
+
+    /// Content given as text (sent UTF-8 encoded).
+    pub fn text(text: impl Into<String>) -> Content {
+        Content::Bytes(text.into().into_bytes())
+    }
+}
+
+/// HTTP response get from routers
+pub struct Response {
+    pub status: Status,
+    pub headers: HeaderMap,
+    pub content: Content,
+}
+
+
+impl Response {
+
+    /// Creates a response with no extra headers.
+    pub fn new(status: Status, content: Content) -> Response {
+        Response { status, headers: HeaderMap::new(), content }
+    }
+
+    /// Sets `value` as the only value of the header `name`, returning the updated response.
+    /// Fails for header names or values that could corrupt the response.
+    pub fn with_header(mut self, name: &str, value: &str) -> Result<Response, InvalidHeader> {
+        self.set_header(name, value)?;
+        Ok(self)
+    }
+
+    /// Sets `value` as the only value of the header `name`.
+    /// Fails for header names or values that could corrupt the response.
+    pub fn set_header(&mut self, name: &str, value: &str) -> Result<(), InvalidHeader> {
+        self.headers.try_insert(name, value)
+    }
+
+    /// Adds a value for the header `name`, keeping previous ones (e.g. for `Set-Cookie`).
+    /// Fails for header names or values that could corrupt the response.
+    pub fn append_header(&mut self, name: &str, value: &str) -> Result<(), InvalidHeader> {
+        self.headers.try_append(name, value)
+    }
+
+    /// Sets the caching policy of the response, instead of the server default for its content.
+    pub fn with_cache(mut self, policy: &CachePolicy) -> Response {
+        policy.apply(&mut self.headers);
+        self
+    }
+
+
+    /// Resolves the content with the default settings and `server_path` as the directory of
+    /// server files. See `resolve()`.
+    pub fn into_text_response(self, server_path: &Path) -> TextResponse {
+        self.resolve(&ResolveContext {
+            server_path,
+            mime_types: &MimeTypes::default(),
+            cache_defaults: &CacheDefaults::default(),
+            accept_encoding: &[],
+        })
+    }
+
+
+    /// Resolves the content into the body to send: files are opened (and given a
+    /// `Content-Type`, `ETag` and `Last-Modified`, unless the response has them already), and
+    /// unknown routes become the server `404.html` page. Responses without a `Cache-Control`
+    /// get the default policy for their content.
+    ///
+    /// Server files with precompressed siblings (`file.br` or `file.gz`) are sent as the
+    /// sibling in the best coding the client accepts, with the `Content-Type` of the original.
+    /// Siblings of user files (like those of `StaticFiles`) are not looked up.
+    pub fn resolve(self, context: &ResolveContext) -> TextResponse {
+
+        use Content::*;
+
+        let mut response = self;
+
+        loop {
+            // Transform `response` until we get `Bytes`
+            response = match response.content {
+
+                Bytes(bytes) => {
+                    context.cache_defaults.generated.apply_default(&mut response.headers);
+                    let body = Body::Bytes(bytes);
+                    return TextResponse::with_app_headers(response.status, response.headers, body);
+                },
+
+                Stream(reader) => {
+                    context.cache_defaults.generated.apply_default(&mut response.headers);
+                    let length = response.headers.get("Content-Length")
+                        .and_then(|length| length.parse().ok());
+                    let body = Body::Stream { reader, length };
+                    return TextResponse::with_app_headers(response.status, response.headers, body);
+                },
+
+                UserFile(abs_path) => {
+                    let opened = open_file(&abs_path).and_then(|(mut file, metadata)| {
+                        if !response.headers.contains("Content-Type") {
+                            let media_type = detect_type(&abs_path, &mut file, context.mime_types)?;
+                            response.headers.insert("Content-Type", media_type);
+                        }
+                        add_validators(&mut response.headers, &metadata);
+                        context.cache_defaults.files.apply_default(&mut response.headers);
+                        Ok((file, metadata))
+                    });
+                    match opened
+                    {
+                        Ok((file, metadata)) => {
+                            let body = Body::File { file, length: metadata.len() };
+                            return TextResponse::with_app_headers(response.status, response.headers, body);
+                        },
+                        Err(e) => {
+                            error!("Failed to read '{:?}': {:?}", abs_path, e);
+                            Response::new(
+                                Status::InternalError, Content::text("Resource not available.")
+                            )
+                        },
+                    }
+                },
+
+                ServerFile(rel_path) => {
+                    let mut abs_path = context.server_path.to_path_buf();
+                    abs_path.push(rel_path);
+                    let abs_path = select_precompressed(abs_path, &mut response.headers, context);
+                    Response {
+                        content: UserFile(abs_path),
+                        ..response
+                    }
+                },
+
+                UnknownRoute => Response {
+                    status: Status::NotFound,
+                    content: ServerFile("404.html".into()),
+                    ..response
+                },
+            };
+        } // loop
+        // The compiler knows this point is `unreachable!()`.
+    } // fn
+
+} // impl
+
+
+/// Settings for `Response::resolve()`.
+pub struct ResolveContext<'c> {
+    /// Directory of the server files (`Content::ServerFile`).
+    pub server_path: &'c Path,
+    /// Media types for files.
+    pub mime_types: &'c MimeTypes,
+    /// Caching policies for responses that set none.
+    pub cache_defaults: &'c CacheDefaults,
+    /// Elements of the `Accept-Encoding` field of the request, to choose precompressed files.
+    pub accept_encoding: &'c [&'c str],
+}
+
+
+/// Extensions of the precompressed siblings of server files, and their content codings, by
+/// preference.
+const PRECOMPRESSED: &[(&str, &str)] = &[("br", "br"), ("gz", "gzip")];
+
+
+/// Returns the file to send for the server file `path`: its best precompressed sibling
+/// accepted by the client, if any, or else `path` itself. When siblings exist, `headers` get
+/// the `Vary` field for `Accept-Encoding`, and for a sibling also its `Content-Encoding`
+/// and the `Content-Type` of `path`.
+fn select_precompressed(path: PathBuf, headers: &mut HeaderMap, context: &ResolveContext)
+    -> PathBuf
+{
+    if headers.contains("Content-Encoding") {
+        return path;
+    }
+
+    let siblings: Vec<(&str, PathBuf)> = PRECOMPRESSED.iter()
+        .map(|(extension, coding)| {
+            let mut sibling = path.clone().into_os_string();
+            sibling.push(format!(".{extension}"));
+            (*coding, PathBuf::from(sibling))
+        })
+        .filter(|(_, sibling)| sibling.is_file())
+        .collect();
+    if siblings.is_empty() {
+        return path;
+    }
+    if !headers.contains_token("Vary", "Accept-Encoding") {
+        headers.append("Vary", "Accept-Encoding");
+    }
+
+    let mut best: Option<(f32, &str, PathBuf)> = None;
+    for (coding, sibling) in siblings {
+        let quality = compression::quality(context.accept_encoding, coding);
+        if quality > 0.0 && best.as_ref().is_none_or(|(best, _, _)| quality > *best) {
+            best = Some((quality, coding, sibling));
+        }
+    }
+    let Some((_, coding, sibling)) = best else {
+        return path;
+    };
+
+    // The type is that of the original content (which must exist), not of its encoding.
+    if !headers.contains("Content-Type") {
+        let media_type = open_file(&path)
+            .and_then(|(mut file, _)| detect_type(&path, &mut file, context.mime_types));
+        match media_type {
+            Ok(media_type) => headers.insert("Content-Type", media_type),
+            Err(_) => return path,
+        }
+    }
+    headers.insert("Content-Encoding", coding);
+    sibling
+}
+
+
+/// Returns the media type of `file`, sniffing its first bytes if the extension of `path` is
+/// unknown (and sniffing is enabled). The file is left at its start.
+fn detect_type(path: &Path, file: &mut fs::File, mime_types: &MimeTypes) -> io::Result<String> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+
+    if extension.and_then(|extension| mime_types.from_extension(extension)).is_some()
+        || !mime_types.sniffing()
+    {
+        return Ok(mime_types.detect(path, None).to_string());
+    }
+
+    let mut head = Vec::with_capacity(mime::SNIFF_LEN);
+    Read::by_ref(file).take(mime::SNIFF_LEN as u64).read_to_end(&mut head)?;
+    file.rewind()?;
+    Ok(mime_types.detect(path, Some(&head)).to_string())
+}
+
+
+/// Adds the validators of a file (unless already given), used to answer conditional requests.
+/// The entity tag is derived from the size and modification time of the file.
+fn add_validators(headers: &mut HeaderMap, metadata: &fs::Metadata) {
+    let Ok(modified) = metadata.modified() else {
+        return;
+    };
+    let mtime = modified.duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+
+    if !headers.contains("ETag") {
+        let etag = format!("\"{:x}-{:x}.{:x}\"", metadata.len(), mtime.as_secs(), mtime.subsec_nanos());
+        headers.insert("ETag", etag);
+    }
+    if !headers.contains("Last-Modified") {
+        headers.insert("Last-Modified", DateTime::from_system_time(modified).to_http_date());
+    }
+}
+
+
+/// Opens a regular file for reading.
+fn open_file(path: &Path) -> io::Result<(fs::File, fs::Metadata)> {
+    let file = fs::File::open(path)?;
+    let metadata = file.metadata()?;
+
+    if metadata.is_file() {
+        Ok((file, metadata))
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a regular file"))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_dir::TestDir;
+
+    fn serialize(response: TextResponse) -> String {
+        let mut out = Vec::new();
+        response.write_to(Version::Http11, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(Status::NotFound.to_string(), "404 Not Found");
+        assert_eq!(Status::Custom(299, "Fine".into()).to_string(), "299 Fine");
+        assert_eq!(Status::from_code(308), Status::PermanentRedirect);
+        assert_eq!(Status::from_code(599), Status::Custom(599, String::new()));
+        assert!(Status::from_code(429).is_client_error());
+
+        let response = TextResponse::new(Status::ImUsed, "".into());
+        let mut out = Vec::new();
+        response.write_to(Version::Http10, &mut out).unwrap();
+        assert!(out.starts_with(b"HTTP/1.0 226 IM Used\r\n"));
+
+        let injected = Status::Custom(200, "OK\r\nSet-Cookie: evil=1".into());
+        assert!(serialize(TextResponse::new(injected, "".into())).starts_with("HTTP/1.1 200 \r\nContent-Length"));
+        for code in [5, 1000] {
+            let invalid = TextResponse::new(Status::Custom(code, "Odd".into()), "".into());
+            assert!(serialize(invalid).starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+        }
+    }
+
+    #[test]
+    fn test_write_stream() {
+        let reader = Box::new(&b"0123456789"[..]);
+        let sized = TextResponse::new(Status::OK, Body::Stream { reader, length: Some(4) });
+        assert!(serialize(sized).ends_with("Content-Length: 4\r\n\r\n0123"));
+
+        let reader = Box::new(io::repeat(b'x').take(STREAM_CHUNK_LEN as u64 + 2));
+        let chunked = serialize(TextResponse::new(Status::OK, Body::Stream { reader, length: None }));
+        let (head, body) = chunked.split_once("\r\n\r\n").unwrap();
+        assert!(head.ends_with("Transfer-Encoding: chunked"));
+        assert_eq!(body, format!("4000\r\n{}\r\n2\r\nxx\r\n0\r\n\r\n", "x".repeat(STREAM_CHUNK_LEN)));
+
+        let reader = Box::new(&b"012"[..]);
+        let short = TextResponse::new(Status::OK, Body::Stream { reader, length: Some(4) });
+        assert!(short.write_to(Version::Http11, &mut Vec::new()).is_err());
+
+        let reader = Box::new(&b"0123456789"[..]);
+        let unsized_10 = TextResponse::new(Status::OK, Body::Stream { reader, length: None });
+        let mut out = Vec::new();
+        unsized_10.write_to(Version::Http10, &mut out).unwrap();
+        assert!(out.ends_with(b"200 OK\r\n\r\n0123456789"));
+
+        let no_content = serialize(TextResponse::new(Status::NoContent, "ignored".into()));
+        assert_eq!(no_content, "HTTP/1.1 204 No Content\r\n\r\n");
+        assert!(!no_content.contains("Content-Length"));
+
+        let reader = Box::new(io::repeat(b'x'));
+        let head = TextResponse::new(Status::OK, Body::Stream { reader, length: Some(4) });
+        assert!(serialize(head.without_body()).ends_with("Content-Length: 4\r\n\r\n"));
+        let head = TextResponse::new(Status::OK, "0123456789".into()).without_body();
+        assert!(serialize(head).ends_with("Content-Length: 10\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_resolve_type() {
+        let dir = TestDir::new("resolve");
+        fs::write(dir.join("notes.md"), "# Notes").unwrap();
+        fs::write(dir.join("image"), b"GIF89a\x01\0\x01\0").unwrap();
+
+        let mime_types = MimeTypes::new().with_type("md", "text/x-markdown");
+        let cache_defaults = CacheDefaults::default();
+        let context = ResolveContext {
+            server_path: &dir, mime_types: &mime_types, cache_defaults: &cache_defaults,
+            accept_encoding: &[],
+        };
+        let resolve = |content, media_type: Option<&str>| {
+            let mut response = Response::new(Status::OK, content);
+            if let Some(media_type) = media_type {
+                response.headers.insert("Content-Type", media_type);
+            }
+            serialize(response.resolve(&context))
+        };
+
+        let notes = resolve(Content::ServerFile("notes.md".into()), None);
+        assert!(notes.contains("Content-Type: text/x-markdown\r\n"));
+        let image = resolve(Content::UserFile(dir.join("image")), None);
+        assert!(image.contains("Content-Type: image/gif\r\n") && image.ends_with("GIF89a\x01\0\x01\0"));
+        assert!(image.contains("ETag: \"a-") && image.contains("Last-Modified: "));
+        assert!(image.contains("Cache-Control: no-cache\r\n"));
+        assert!(resolve(Content::text("generated"), None).contains("Cache-Control: no-store\r\n"));
+        let image = resolve(Content::UserFile(dir.join("image")), Some("image/x-test"));
+        assert!(image.contains("Content-Type: image/x-test\r\n"));
+    }
+
+    #[test]
+    fn test_resolve_precompressed() {
+        let dir = TestDir::new("precompressed");
+        fs::write(dir.join("app.js"), "original").unwrap();
+        fs::write(dir.join("app.js.gz"), "gzipped").unwrap();
+        fs::write(dir.join("app.js.br"), "brotli").unwrap();
+        fs::write(dir.join("plain.css"), "plain").unwrap();
+
+        let (mime_types, cache_defaults) = (MimeTypes::default(), CacheDefaults::default());
+        let resolve = |path: &str, accept_encoding: &[&str]| {
+            let context = ResolveContext {
+                server_path: &dir, mime_types: &mime_types, cache_defaults: &cache_defaults,
+                accept_encoding,
+            };
+            let response = Response::new(Status::OK, Content::ServerFile(path.into()));
+            serialize(response.resolve(&context))
+        };
+
+        let gzipped = resolve("app.js", &["gzip", "br;q=0.5"]);
+        assert!(gzipped.contains("Content-Type: text/javascript; charset=utf-8\r\n"));
+        assert!(gzipped.contains("Content-Encoding: gzip\r\n") && gzipped.ends_with("\r\n\r\ngzipped"));
+        assert!(resolve("app.js", &["*"]).ends_with("\r\n\r\nbrotli"));
+
+        let original = resolve("app.js", &["identity"]);
+        assert!(original.contains("Vary: Accept-Encoding\r\n") && !original.contains("Content-Encoding"));
+        assert!(original.ends_with("\r\n\r\noriginal"));
+        let plain = resolve("plain.css", &["gzip"]);
+        assert!(!plain.contains("Vary") && plain.ends_with("\r\n\r\nplain"));
+    }
+}
@@ -1,26 +1,54 @@
 use std::{
-    error::Error,
-    io::prelude::*,
+    io::{BufWriter, Read},
     net::{TcpListener, TcpStream},
     path::PathBuf,
-    sync::{atomic::{AtomicBool, Ordering}, Arc}
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::Duration,
 };
 
-use log::{info, warn, error, debug, trace};
+use log::{info, warn, error, debug};
 use clap::Args;
-use ctrlc;
 
 mod thread_pool;
 use crate::thread_pool::ThreadPool;
 
 mod uri; // Used inside module http
 
+pub mod error;
+pub use error::Error;
+mod state; // Used inside module http
+
 pub mod http; // `pub` to re-export as part of the library interface
-pub type Request  = http::req::Request;
+pub type Request<'a> = http::req::Request<'a>;
 pub type Response = http::res::Response;
 pub type Status   = http::res::Status;
 pub type Content  = http::res::Content;
 pub type Method   = http::req::Method;
+pub type HeaderMap = http::HeaderMap;
+pub type Version  = http::Version;
+
+pub mod handler;
+pub use handler::{Handler, IntoResponse};
+
+pub mod router;
+pub type Router = router::Router;
+
+pub mod middleware;
+pub use middleware::Middleware;
+
+pub mod mime;
+
+pub mod cache;
+pub type CachePolicy = cache::CachePolicy;
+
+pub mod compression;
+pub type Compression = compression::Compression;
+
+pub mod static_files;
+pub type StaticFiles = static_files::StaticFiles;
+
+#[cfg(test)]
+mod test_dir; // Used by the tests of file content
 
 
 // `ServerConfig` is the application configuration definition with embeded
@@ -42,6 +70,26 @@ pub struct ServerConfig {
     #[arg(short, long, default_value_t=8)]
     pub threads: usize,
 
+    /// Maximum size in bytes of a request body; larger requests are rejected with
+    /// `413 Payload Too Large`
+    #[arg(long, default_value_t=16 * 1024 * 1024)]
+    pub max_body_size: usize,
+
+    /// Request bodies up to this size in bytes are read into memory before routing;
+    /// larger ones are read on demand through `Body::reader()`
+    #[arg(long, default_value_t=64 * 1024)]
+    pub body_buffer_size: usize,
+
+    /// Seconds a persistent (keep-alive) connection may stay idle waiting for the next
+    /// request before the server closes it; `0` disables persistent connections. Each open
+    /// connection holds a worker thread
+    #[arg(long, default_value_t=5)]
+    pub keep_alive_timeout: u64,
+
+    /// Maximum number of requests served on a single connection before closing it
+    #[arg(long, default_value_t=100)]
+    pub max_requests_per_connection: usize,
+
     #[arg(skip)]
     pub resource_dir: PathBuf,
 }
@@ -52,88 +100,258 @@ pub struct ServerConfig {
 /// (Therefore a dummy connection is required to signal the server finalization.)
 ///
 /// All requests are processed by the given `router` closure. The parsed `Request` is passed to it,
-/// and the `Response` it returns is used as the server response for that specific request.
+/// and the response it returns (any `IntoResponse` value) is used as the server response for that
+/// specific request.
+/// A `Router` is used by passing `move |request| router.handle(request)`.
 ///
-/// All network and runtime configuration is passed in `config`.
+/// All network and runtime configuration is passed in `config`. To add middleware layers, use
+/// `Server` instead.
 ///
-pub fn run<F>(enabled: Arc<AtomicBool>, config: ServerConfig, router: F) -> Result<(), Box<dyn Error>>
+pub fn run<F, R>(enabled: Arc<AtomicBool>, config: ServerConfig, router: F) -> Result<(), Box<dyn std::error::Error>>
 where
-    F: Fn(&http::Request) -> Result<http::Response, Box<dyn Error>> + Send + 'static + Sync
+    F: Fn(&http::Request) -> R + Send + 'static + Sync,
+    R: IntoResponse,
 {
+    Server::new(config, router).run(enabled)
+}
+
 
-    let bind_address = format!("{}:{}", config.interface_address, config.port);
-    info!("Binding server to {bind_address}");
+/// An HTTP server: its configuration, the handler for all requests, the middleware layers
+/// around it, and the application state available to them.
+///
+/// Example:
+/// ```no_run
+/// # fn example(config: shttp::ServerConfig, router: shttp::Router, counter: impl shttp::Middleware)
+/// #     -> Result<(), Box<dyn std::error::Error>> {
+/// let enabled = shttp::set_ctrlc_finalizer(&config);
+/// shttp::Server::new(config, router)
+///     .state(std::sync::Mutex::new(0_usize))
+///     .layer(counter)
+///     .run(enabled)
+/// # }
+/// ```
+pub struct Server {
+    config: ServerConfig,
+    handler: Box<dyn Handler>,
+    layers: Vec<Box<dyn Middleware>>,
+    state: Arc<state::State>,
+    mime_types: mime::MimeTypes,
+    cache_defaults: cache::CacheDefaults,
+}
 
-    let listener = TcpListener::bind(bind_address)?;
-    let pool = ThreadPool::new(config.threads);
-    let shared_config = Arc::new(config);
-    let shared_router = Arc::new(router);
+impl Server {
 
-    for stream_result in listener.incoming() {
-        if !enabled.load(Ordering::Acquire) {
-            break;
+    /// Creates a server that passes every request to `handler` (e.g. a `Router`, or a closure
+    /// with its parameter annotated as `|request: &Request|`).
+    pub fn new(config: ServerConfig, handler: impl Handler) -> Server {
+        Server {
+            config, handler: Box::new(handler), layers: vec![], state: Arc::default(),
+            mime_types: mime::MimeTypes::default(), cache_defaults: cache::CacheDefaults::default(),
         }
-        let stream = stream_result.or_else(|e| Err(e))?; // graceful unwrap().
-        let shared_config = Arc::clone(&shared_config);
-        let shared_router = Arc::clone(&shared_router);
-        pool.execute(move || {
-            handle_connection(stream, shared_config, shared_router);
-        });
     }
 
-    info!("Server closed, not more connections will be accepted.");
+    /// Sets the media types for file content, e.g. to add extensions or disable sniffing.
+    pub fn mime_types(mut self, mime_types: mime::MimeTypes) -> Server {
+        self.mime_types = mime_types;
+        self
+    }
 
-    Ok(()) // Everything was OK.
-}
+    /// Sets the caching policies of responses that set none, by the kind of their content.
+    pub fn cache_defaults(mut self, cache_defaults: cache::CacheDefaults) -> Server {
+        self.cache_defaults = cache_defaults;
+        self
+    }
 
+    /// Registers `value` as application state, shared by all requests and retrieved by its
+    /// type with `Request::state::<T>()`. Only one value of each type is kept: mutable state
+    /// needs a type with interior mutability (e.g. `RwLock<AppState>`), and values of common
+    /// types are best wrapped in an application specific type.
+    pub fn state<T: Send + Sync + 'static>(mut self, value: T) -> Server {
+        Arc::get_mut(&mut self.state)
+            .expect("State is only shared once the server runs")
+            .insert(value);
+        self
+    }
 
-/// Processes the connection given in `stream` by reading and parsing it as an HTTP `Request` that
-/// then is passed to the user-provided HTTP `router` closure, which is expected to return a
-/// structured HTTP `Response` that finally is serialized and written back to `stream`.
-///
-fn handle_connection<F>(mut stream: TcpStream, config: Arc<ServerConfig>, router: Arc<F>)
-where
-    F: Fn(&http::Request) -> Result<http::Response, Box<dyn Error>> + Send + 'static + Sync
-{
-    let text_response = match http::Request::parse_from_stream(&mut stream)
-    {
-        Ok(request) => {
-            info!("Got request: {:?}", request.method);
-            debug!("Request header: {:?}", request);
-            match router(&request)
-            {
-                Ok(response) => response.into_text_response(&config.resource_dir),
+    /// Adds a middleware layer inside the previous ones (see `Middleware` for the order
+    /// in which they run).
+    pub fn layer(mut self, layer: impl Middleware) -> Server {
+        self.layers.push(Box::new(layer));
+        self
+    }
+
+    /// Runs the server until `enabled` becomes `false`, as described for `run`.
+    pub fn run(self, enabled: Arc<AtomicBool>) -> Result<(), Box<dyn std::error::Error>> {
+
+        let bind_address = format!("{}:{}", self.config.interface_address, self.config.port);
+        info!("Binding server to {bind_address}");
+
+        let listener = TcpListener::bind(bind_address)?;
+        let pool = ThreadPool::new(self.config.threads);
+        let shared_server = Arc::new(self);
+
+        for stream_result in listener.incoming() {
+            if !enabled.load(Ordering::Acquire) {
+                break;
+            }
+            let stream = stream_result?; // graceful unwrap().
+            let shared_server = Arc::clone(&shared_server);
+            let enabled = Arc::clone(&enabled);
+            pool.execute(move || {
+                shared_server.handle_connection(stream, enabled);
+            });
+        }
+
+        info!("Server closed, not more connections will be accepted.");
+
+        Ok(()) // Everything was OK.
+    }
+
+
+    /// Processes the connection given in `stream` by reading and parsing it as an HTTP `Request` that
+    /// then is passed through the middleware to the handler, which is expected to return a
+    /// structured HTTP `Response` that finally is serialized and written back to `stream`.
+    ///
+    /// Persistent connections are kept open for further requests until either side asks to close
+    /// them, they stay idle for too long, they reach the maximum number of requests, or the server
+    /// is disabled.
+    ///
+    fn handle_connection(&self, stream: TcpStream, enabled: Arc<AtomicBool>) {
+        let config = &self.config;
+        let idle_timeout = Duration::from_secs(config.keep_alive_timeout);
+        let keep_alive_enabled = !idle_timeout.is_zero();
+
+        if keep_alive_enabled {
+            if let Err(error) = stream.set_read_timeout(Some(idle_timeout)) {
+                warn!("Failed to set connection timeout: {error}");
+            }
+        }
+
+        // Requests are read through a buffer (which may hold the start of the next ones), while
+        // responses are written directly to the stream.
+        let mut connection = http::req::ConnectionReader::new(&stream);
+
+        for request_num in 1.. {
+            match connection.wait_for_request() {
+                Ok(true) => {},
+                Ok(false) => break, // Closed by the client
                 Err(error) => {
-                    error!("Router failed to process request: {error}");
-                    http::res::TextResponse {
-                        status: http::res::Status::InternalError,
-                        body: "Failed to process resquest".into(),
-                    }
-                }
+                    debug!("Closing idle connection: {error}");
+                    break;
+                },
             }
-        },
-        Err(error) => {
-            error!("Bad request: {error}");
-            http::res::TextResponse {
-                status: http::res::Status::BadRequest,
-                body: "Bad request".into(),
+
+            let (version, mut text_response, mut keep_alive) = self.process_request(&mut connection);
+
+            keep_alive = keep_alive
+                && keep_alive_enabled
+                && request_num < config.max_requests_per_connection
+                && enabled.load(Ordering::Acquire)
+                && !text_response.headers.contains_token("Connection", "close")
+                // Without chunked coding, only closing the connection delimits such a body:
+                && !(version == http::Version::Http10 && matches!(
+                    text_response.body, http::res::Body::Stream { length: None, .. }
+                ));
+
+            if !keep_alive {
+                text_response.headers.insert("Connection", "close");
+            } else if version == http::Version::Http10 {
+                text_response.headers.insert("Connection", "keep-alive");
             }
-        },
-    };
 
-    send_response(&mut stream, &text_response);
-}
+            // After a failed write the client cannot tell where the response ends, so nothing
+            // else may be sent on the connection.
+            if let Err(error) = send_response(&stream, version, text_response) {
+                error!("Failed to write response: {error}");
+                break;
+            }
+
+            if !keep_alive {
+                break;
+            }
+        }
+    }
 
 
-/// Serializes the given `response` and writes it to `stream`.
-fn send_response(stream: &mut TcpStream, response: &http::res::TextResponse) {
+    /// Reads the next request from `connection` and resolves its response through the
+    /// middleware and the handler. Returns the protocol version to answer with, the response,
+    /// and whether the connection can be kept open afterwards (as far as the request is
+    /// concerned).
+    fn process_request(&self, connection: &mut http::req::ConnectionReader<&TcpStream>)
+        -> (http::Version, http::res::TextResponse, bool)
+    {
+        let config = &self.config;
+        let limits = http::req::BodyLimits {
+            buffer_size: config.body_buffer_size,
+            max_size: config.max_body_size,
+        };
 
-    let raw_response = response.as_string();
+        let mut request = match connection.next_request(&limits) {
+            Ok(request) => request,
+            Err(error) => {
+                // The rest of the stream cannot be trusted, so the connection is closed.
+                // Requests that cannot be parsed are answered with the latest known version.
+                error!("Rejected request: {error}");
+                let text_response = self.resolve(error.to_response(), &[]);
+                return (http::Version::Http11, text_response, false);
+            },
+        };
 
-    trace!("Response: {:#?}", raw_response);
-    stream.write_all(raw_response.as_bytes()).unwrap_or_else(|error| {
-        error!("Failed to write response: {:?}", error);
-    });
+        info!("Got request: {} {}", request.method, request.target);
+        debug!("Request header: {:?}", request);
+        request.set_state(Arc::clone(&self.state));
+        let response = middleware::dispatch(&self.layers, self.handler.as_ref(), &mut request);
+
+        // A streamed body may turn out to be invalid only while the router reads it:
+        if let Some(body_error) = request.body.error() {
+            let error = Error::from(body_error);
+            error!("Rejected request: {error}");
+            let text_response = self.resolve(error.to_response(), &[]);
+            return (request.version, text_response, false);
+        }
+
+        let text_response = self.resolve(response, &request.headers.get_list("Accept-Encoding"));
+        let text_response = http::conditional::evaluate(&request, text_response);
+        let mut text_response = http::range::evaluate(&request, text_response);
+
+        // Answered with the headers a GET would get (files are opened, and only their first
+        // bytes may be read to detect their type).
+        if request.method == http::req::Method::Head {
+            text_response = text_response.without_body();
+        }
+
+        // Whatever the router left unread of the body must be skipped to reach the next request.
+        let body_left = request.body.reader()
+            .take(config.body_buffer_size as u64 + 1)
+            .read_to_end(&mut Vec::new());
+        let keep_alive = request.keep_alive()
+            && body_left.is_ok_and(|_| request.body.is_complete());
+
+        (request.version, text_response, keep_alive)
+    }
+
+
+    /// Resolves the content of `response` into the body to send, choosing precompressed
+    /// files among the codings in `accept_encoding`.
+    fn resolve(&self, response: http::Response, accept_encoding: &[&str])
+        -> http::res::TextResponse
+    {
+        response.resolve(&http::res::ResolveContext {
+            server_path: &self.config.resource_dir,
+            mime_types: &self.mime_types,
+            cache_defaults: &self.cache_defaults,
+            accept_encoding,
+        })
+    }
+}
+
+
+/// Serializes the given `response` for a `version` client and writes it to `stream`.
+fn send_response(stream: &TcpStream, version: http::Version, response: http::res::TextResponse)
+    -> std::io::Result<()>
+{
+    let mut writer = BufWriter::new(stream);
+    response.write_to(version, &mut writer)
 }
 
 
@@ -168,3 +386,76 @@ pub fn set_ctrlc_finalizer(config: &ServerConfig) -> Arc<AtomicBool> {
 
     is_server_enabled
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{atomic::AtomicBool, Arc};
+
+    use clap::{Args, Command, FromArgMatches};
+
+    use super::{Server, ServerConfig};
+    use crate::http::{Request, Response, res::{Content, Status}};
+    use crate::test_dir::TestDir;
+
+    /// Serves `requests` (written at once) on a connection to `server`, and returns all that
+    /// the client receives.
+    fn exchange(server: &Server, requests: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client.write_all(requests.as_bytes()).unwrap();
+        server.handle_connection(stream, Arc::new(AtomicBool::new(true)));
+
+        let mut received = vec![];
+        let _ = client.read_to_end(&mut received); // The connection may be reset
+        String::from_utf8_lossy(&received).into_owned()
+    }
+
+    fn config() -> ServerConfig {
+        let command = ServerConfig::augment_args(Command::new("test"));
+        ServerConfig::from_arg_matches(&command.get_matches_from(["test"])).unwrap()
+    }
+
+    #[test]
+    fn test_failed_write_closes_connection() {
+        // Declares 10 bytes, but the stream ends after 3.
+        let server = Server::new(config(), |request: &Request| {
+            if request.path != "/short" {
+                return Response::new(Status::PartialContent, Content::text("next"));
+            }
+            let mut response = Response::new(Status::OK, Content::Stream(Box::new(&b"abc"[..])));
+            response.headers.insert("Content-Length", "10");
+            response
+        });
+
+        let received = exchange(&server, "GET /short HTTP/1.1\r\n\r\nGET /next HTTP/1.1\r\n\r\n");
+        assert!(received.starts_with("HTTP/1.1 200 OK\r\n"), "{received}");
+        assert!(received.ends_with("Content-Length: 10\r\n\r\nabc"), "{received}");
+    }
+
+    #[test]
+    fn test_head_matches_get() {
+        let dir = TestDir::new("head");
+        let path = dir.join("image"); // Typed by sniffing, without an extension
+        std::fs::write(&path, b"GIF89a\x01\0\x01\0").unwrap();
+        let server = Server::new(config(), move |_: &Request| {
+            Response::new(Status::OK, Content::UserFile(path.clone()))
+        });
+
+        let received = exchange(&server,
+            "HEAD /image HTTP/1.1\r\n\r\nGET /image HTTP/1.1\r\nConnection: close\r\n\r\n");
+        let (head, get) = received.split_once("\r\n\r\n").unwrap();
+        let get = get.strip_suffix("GIF89a\x01\0\x01\0").unwrap();
+        let field = |response: &str, name: &str| {
+            response.lines().find(|line| line.starts_with(name)).map(String::from)
+        };
+        assert_eq!(field(head, "Content-Type:").as_deref(), Some("Content-Type: image/gif"));
+        for name in ["Content-Type:", "Content-Length:", "ETag:"] {
+            assert!(field(head, name).is_some() && field(head, name) == field(get, name), "{received}");
+        }
+    }
+}
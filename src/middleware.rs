@@ -0,0 +1,148 @@
+//! Middleware: layers of processing around the request handler, for concerns shared by all
+//! the routes (logging, counting, authentication, response headers...).
+
+use std::error::Error;
+
+use log::{debug, error};
+
+use crate::handler::Handler;
+use crate::http::{Request, Response, res::Status};
+
+
+/// A layer around the request handler, added with `Server::layer()`.
+///
+/// Layers run in the order they were added: `before()` from the first to the last one and then
+/// the handler, and `after()` from the last to the first one (so the first layer sees the final
+/// response). Both hooks do nothing by default.
+///
+/// Errors of the handler or of a layer are turned into their response (see `shttp::Error`)
+/// right away, so the `after()` hooks of the layers around it see it like any other response.
+pub trait Middleware: Send + Sync + 'static {
+
+    /// Called before the handler, with the request still mutable. Returning a response
+    /// short-circuits the handler and the next layers: only the `after()` hooks of this and the
+    /// previous layers are applied to it.
+    fn before(&self, _request: &mut Request) -> Result<Option<Response>, Box<dyn Error>> {
+        Ok(None)
+    }
+
+    /// Called with the response of the handler (or of a short-circuiting layer), and returns
+    /// the response to use instead.
+    fn after(&self, _request: &Request, response: Response) -> Result<Response, Box<dyn Error>> {
+        Ok(response)
+    }
+}
+
+
+/// Runs `request` through `layers` and then `handler`, as described for `Middleware`.
+pub(crate) fn dispatch(layers: &[Box<dyn Middleware>], handler: &dyn Handler, request: &mut Request)
+    -> Response
+{
+    let Some((layer, inner)) = layers.split_first() else {
+        return handler.handle(request).unwrap_or_else(error_response);
+    };
+
+    let response = match layer.before(request) {
+        Ok(Some(response)) => response,
+        Ok(None) => dispatch(inner, handler, request),
+        Err(error) => error_response(error),
+    };
+    layer.after(request, response).unwrap_or_else(error_response)
+}
+
+
+/// Returns the response for an error of the handler or a layer: its own for an `shttp::Error`,
+/// or else `500 Internal Server Error` (the details are only logged).
+fn error_response(error: Box<dyn Error>) -> Response {
+    match error.downcast_ref::<crate::Error>() {
+        Some(error @ crate::Error::Handler { .. }) => debug!("Handler answered with: {error}"),
+        _ => error!("Handler failed to process request: {error}"),
+    }
+    match error.downcast::<crate::Error>() {
+        Ok(error) => error.to_response(),
+        Err(_) => crate::Error::status(Status::InternalError, "Failed to process request").to_response(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error;
+    use std::sync::Mutex;
+
+    use super::{dispatch, Middleware};
+    use crate::handler::Handler;
+    use crate::http::{Request, Response, res::{Content, Status}};
+
+    /// Records its hook calls in `log`, and short-circuits requests to `/stop/{name}`.
+    struct Tracer {
+        name: &'static str,
+        log: &'static Mutex<Vec<String>>,
+    }
+
+    impl Middleware for Tracer {
+        fn before(&self, request: &mut Request) -> Result<Option<Response>, Box<dyn Error>> {
+            self.log.lock().unwrap().push(format!("before {}", self.name));
+            request.headers.append("X-Layers", self.name);
+            if request.path == format!("/stop/{}", self.name) {
+                return Ok(Some(Response::new(Status::Forbidden, Content::text(self.name))));
+            }
+            Ok(None)
+        }
+
+        fn after(&self, _request: &Request, mut response: Response)
+            -> Result<Response, Box<dyn Error>>
+        {
+            self.log.lock().unwrap().push(format!("after {}", self.name));
+            response.append_header("X-Seen-By", self.name)?;
+            Ok(response)
+        }
+    }
+
+    #[test]
+    fn test_middleware_order() {
+        static LOG: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+        let layers: Vec<Box<dyn Middleware>> = vec![
+            Box::new(Tracer { name: "outer", log: &LOG }),
+            Box::new(Tracer { name: "inner", log: &LOG }),
+        ];
+        let handler = |request: &Request| {
+            LOG.lock().unwrap().push("handler".into());
+            match request.path.as_str() {
+                "/taken"    => Err(crate::Error::status(Status::Conflict, "Taken"))?,
+                "/fail"     => Err("Failed")?,
+                _           => {},
+            }
+            let layers: Vec<_> = request.headers.get_all("X-Layers").collect();
+            Ok(Response::new(Status::OK, Content::text(layers.join(","))))
+        };
+
+        let mut request = Request::parse("GET /page HTTP/1.1\r\n").unwrap();
+        let response = dispatch(&layers, &handler as &dyn Handler, &mut request);
+        assert_eq!(response.status, Status::OK);
+        assert!(matches!(&response.content, Content::Bytes(bytes) if bytes == b"outer,inner"));
+        assert_eq!(response.headers.get_all("X-Seen-By").collect::<Vec<_>>(), ["inner", "outer"]);
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["before outer", "before inner", "handler", "after inner", "after outer"]
+        );
+
+        let mut request = Request::parse("GET /stop/outer HTTP/1.1\r\n").unwrap();
+        let response = dispatch(&layers, &handler as &dyn Handler, &mut request);
+        assert_eq!(response.status, Status::Forbidden);
+        assert_eq!(response.headers.get_all("X-Seen-By").collect::<Vec<_>>(), ["outer"]);
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            ["before outer", "after outer"]
+        );
+
+        // Errors reach the layers as their response:
+        for (path, status) in [("/taken", Status::Conflict), ("/fail", Status::InternalError)] {
+            let mut request = Request::parse(&format!("GET {path} HTTP/1.1\r\n")).unwrap();
+            let response = dispatch(&layers, &handler as &dyn Handler, &mut request);
+            assert_eq!(response.status, status);
+            assert_eq!(response.headers.get_all("X-Seen-By").collect::<Vec<_>>(), ["inner", "outer"]);
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Media types (MIME types) of files, by their extension or their first bytes.
+
+use std::path::Path;
+
+
+/// Media type for content of unknown type.
+pub const DEFAULT_TYPE: &str = "application/octet-stream";
+
+/// Number of bytes needed by `sniff()`.
+pub const SNIFF_LEN: usize = 512;
+
+
+/// Known file extensions (in lowercase) and their media types.
+const EXTENSION_TYPES: &[(&str, &str)] = &[
+    // Text
+    ("css",     "text/css; charset=utf-8"),
+    ("csv",     "text/csv; charset=utf-8"),
+    ("htm",     "text/html; charset=utf-8"),
+    ("html",    "text/html; charset=utf-8"),
+    ("js",      "text/javascript; charset=utf-8"),
+    ("md",      "text/markdown; charset=utf-8"),
+    ("mjs",     "text/javascript; charset=utf-8"),
+    ("txt",     "text/plain; charset=utf-8"),
+    // Structured data
+    ("json",    "application/json"),
+    ("map",     "application/json"),
+    ("wasm",    "application/wasm"),
+    ("webmanifest", "application/manifest+json"),
+    ("xml",     "application/xml"),
+    // Images
+    ("avif",    "image/avif"),
+    ("bmp",     "image/bmp"),
+    ("gif",     "image/gif"),
+    ("ico",     "image/vnd.microsoft.icon"),
+    ("jpeg",    "image/jpeg"),
+    ("jpg",     "image/jpeg"),
+    ("png",     "image/png"),
+    ("svg",     "image/svg+xml"),
+    ("webp",    "image/webp"),
+    // Fonts
+    ("otf",     "font/otf"),
+    ("ttf",     "font/ttf"),
+    ("woff",    "font/woff"),
+    ("woff2",   "font/woff2"),
+    // Audio and video
+    ("mp3",     "audio/mpeg"),
+    ("mp4",     "video/mp4"),
+    ("oga",     "audio/ogg"),
+    ("ogg",     "audio/ogg"),
+    ("ogv",     "video/ogg"),
+    ("wav",     "audio/wav"),
+    ("weba",    "audio/webm"),
+    ("webm",    "video/webm"),
+    // Documents and archives
+    ("epub",    "application/epub+zip"),
+    ("gz",      "application/gzip"),
+    ("pdf",     "application/pdf"),
+    ("tar",     "application/x-tar"),
+    ("zip",     "application/zip"),
+];
+
+
+/// Signatures (at the start of the content, `?` matching any byte) and their media types.
+const MAGIC_BYTES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n",  "image/png"),
+    (b"\xff\xd8\xff",       "image/jpeg"),
+    (b"GIF87a",             "image/gif"),
+    (b"GIF89a",             "image/gif"),
+    (b"RIFF????WEBP",       "image/webp"),
+    (b"RIFF????WAVE",       "audio/wav"),
+    (b"%PDF-",              "application/pdf"),
+    (b"PK\x03\x04",         "application/zip"),
+    (b"\x1f\x8b",           "application/gzip"),
+    (b"\0asm",              "application/wasm"),
+    (b"wOFF",               "font/woff"),
+    (b"wOF2",               "font/woff2"),
+    (b"OggS",               "audio/ogg"),
+    (b"ID3",                "audio/mpeg"),
+    (b"????ftyp",           "video/mp4"),
+    (b"\x1a\x45\xdf\xa3",   "video/webm"),
+];
+
+
+/// Returns the media type for a file extension (case-insensitive), if known.
+pub fn from_extension(extension: &str) -> Option<&'static str> {
+    EXTENSION_TYPES.iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+        .map(|(_, media_type)| *media_type)
+}
+
+
+/// Guesses the media type of content from its first bytes (up to `SNIFF_LEN`): either by the
+/// signature of a known binary format, or as plain text for UTF-8 without control characters.
+/// Markup like HTML is never guessed, as browsers would run its scripts.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    let bytes = &bytes[..bytes.len().min(SNIFF_LEN)];
+
+    let signature = MAGIC_BYTES.iter().find(|(magic, _)|
+        bytes.len() >= magic.len()
+            && magic.iter().zip(bytes).all(|(&m, &b)| m == b'?' || m == b)
+    );
+    if let Some((_, media_type)) = signature {
+        return Some(media_type);
+    }
+
+    // A multi-byte character may have been cut at the end.
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&bytes[..e.valid_up_to()]).ok()?,
+        Err(_) => return None,
+    };
+    let is_text = !text.is_empty()
+        && !text.chars().any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'));
+
+    is_text.then_some("text/plain; charset=utf-8")
+}
+
+
+/// Media types used for files, from the built-in table, application overrides and (if
+/// enabled) sniffing of their content.
+#[derive(Debug, Clone)]
+pub struct MimeTypes {
+    overrides: Vec<(String, String)>,
+    sniffing: bool,
+}
+
+impl Default for MimeTypes {
+    fn default() -> MimeTypes {
+        MimeTypes { overrides: vec![], sniffing: true }
+    }
+}
+
+impl MimeTypes {
+
+    /// Creates the built-in mapping, with sniffing enabled.
+    pub fn new() -> MimeTypes {
+        MimeTypes::default()
+    }
+
+    /// Sets the media type for files with `extension` (case-insensitive), replacing the
+    /// built-in one if any.
+    pub fn with_type(mut self, extension: &str, media_type: &str) -> MimeTypes {
+        self.overrides.retain(|(known, _)| !known.eq_ignore_ascii_case(extension));
+        self.overrides.push((extension.to_string(), media_type.to_string()));
+        self
+    }
+
+    /// Enables or disables guessing the type of files with unknown extensions from their
+    /// content.
+    pub fn with_sniffing(mut self, sniffing: bool) -> MimeTypes {
+        self.sniffing = sniffing;
+        self
+    }
+
+    /// Returns `true` if files with unknown extensions should be sniffed.
+    pub fn sniffing(&self) -> bool {
+        self.sniffing
+    }
+
+    /// Returns the media type for a file extension, if known.
+    pub fn from_extension(&self, extension: &str) -> Option<&str> {
+        self.overrides.iter()
+            .find(|(known, _)| known.eq_ignore_ascii_case(extension))
+            .map(|(_, media_type)| media_type.as_str())
+            .or_else(|| from_extension(extension))
+    }
+
+    /// Returns the media type for the file at `path`, from its extension or else from its
+    /// first bytes `head` (if given and sniffing is enabled), or `DEFAULT_TYPE`.
+    pub fn detect(&self, path: &Path, head: Option<&[u8]>) -> &str {
+        extension(path)
+            .and_then(|extension| self.from_extension(extension))
+            .or_else(|| head.filter(|_| self.sniffing).and_then(sniff))
+            .unwrap_or(DEFAULT_TYPE)
+    }
+}
+
+
+fn extension(path: &Path) -> Option<&str> {
+    path.extension().and_then(|extension| extension.to_str())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{sniff, MimeTypes, DEFAULT_TYPE};
+
+    #[test]
+    fn test_sniff() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(sniff(b"plain text\nwith lines\t\xe2\x82"), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(b"<html><script>alert(1)</script>"), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(b"binary\0data"), None);
+        assert_eq!(sniff(b"\xff\xfe"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn test_mime_types() {
+        let mime_types = MimeTypes::new()
+            .with_type("md", "text/x-markdown")
+            .with_type("LOG", "text/plain");
+
+        assert_eq!(mime_types.detect(Path::new("a/style.CSS"), None), "text/css; charset=utf-8");
+        assert_eq!(mime_types.detect(Path::new("README.md"), None), "text/x-markdown");
+        assert_eq!(mime_types.detect(Path::new("server.log"), None), "text/plain");
+        assert_eq!(mime_types.detect(Path::new("image"), Some(b"GIF89a...")), "image/gif");
+        assert_eq!(mime_types.detect(Path::new("data.bin"), None), DEFAULT_TYPE);
+
+        let mime_types = mime_types.with_sniffing(false);
+        assert_eq!(mime_types.detect(Path::new("image"), Some(b"GIF89a...")), DEFAULT_TYPE);
+    }
+}
@@ -0,0 +1,522 @@
+//! Declarative request routing by method and path pattern.
+//!
+//! Example:
+//! ```no_run
+//! use shttp::{Router, Response, Status, Content, Request};
+//!
+//! let router = Router::builder()
+//!     .get("/users/:id", |request: &Request| Ok(Response::new(
+//!         Status::OK, Content::text(format!("User {}", request.param("id").unwrap()))
+//!     )))
+//!     .get("/files/*path", |request: &Request| Ok(Response::new(
+//!         Status::OK, Content::text(format!("File {}", request.param("path").unwrap()))
+//!     )))
+//!     .build()
+//!     .expect("Invalid routes");
+//! ```
+//!
+//! Patterns are made of `/`-separated segments, each of them either:
+//! - A literal, which must match the request path segment exactly.
+//! - `:name`, which matches any single segment and captures it as parameter `name`.
+//! - `*name`, only as the last segment, which matches the rest of the path (possibly empty)
+//!   and captures it as parameter `name`.
+//!
+//! Empty segments are ignored, so `/users/7/` matches `/users/:id`. When several routes match
+//! a request, the most specific one wins, comparing segments from left to right: literals
+//! before parameters before wildcards.
+
+use std::error::Error;
+use std::fmt;
+
+use crate::handler::{Handler, IntoResponse};
+use crate::http::{Request, Response, req::Method, res::{Content, Status}};
+use crate::uri;
+
+
+/// Routes requests to handlers according to their method and path. Build it with
+/// `Router::builder()`; it can then be passed to `run` as `move |request| router.handle(request)`.
+pub struct Router {
+    routes: Vec<Route>,
+    fallback: Option<Box<dyn Handler>>,
+}
+
+
+struct Route {
+    method: Method,
+    pattern: String,
+    segments: Vec<Segment>,
+    handler: Box<dyn Handler>,
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+
+impl Router {
+
+    pub fn builder() -> RouterBuilder {
+        RouterBuilder { routes: vec![], fallback: None, error: None }
+    }
+
+    /// Passes `request` to the handler of the best matching route, with the captured path
+    /// parameters added to it. `HEAD` requests fall back to the `GET` routes.
+    ///
+    /// When routes match the path but not the method, the request is answered with
+    /// `405 Method Not Allowed`, or with `204 No Content` for `OPTIONS`, listing the methods
+    /// of those routes in an `Allow` header (`OPTIONS *` lists those of all routes). Other
+    /// requests go to the fallback handler, which by default answers `404 Not Found` with
+    /// `Content::UnknownRoute`.
+    pub fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+
+        // Matching is done on raw segments, so that an encoded `/` does not split a segment.
+        let (raw_path, _) = uri::split_target(&request.target);
+        let path = RequestPath::new(raw_path);
+
+        let find_route = |method: &Method| self.routes.iter()
+            .filter(|route| route.method == *method)
+            .filter_map(|route| route.captures(&path).map(|params| (route, params)))
+            .min_by_key(|(route, _)| route.rank());
+
+        // `HEAD` requests without a route of their own are answered like `GET` (the server
+        // then omits the body).
+        let best_match = match &request.method {
+            Method::Head => find_route(&Method::Head).or_else(|| find_route(&Method::Get)),
+            method => find_route(method),
+        };
+
+        if let Some((route, params)) = best_match {
+            request.add_params(params);
+            return route.handler.handle(request);
+        }
+
+        let allowed = match request.target.as_str() {
+            "*" => self.allowed_methods(|_| true),
+            _ => self.allowed_methods(|route| route.captures(&path).is_some()),
+        };
+
+        match (&request.method, allowed, &self.fallback) {
+            (Method::Options, Some(allowed), _) =>
+                Ok(Response::new(Status::NoContent, Content::Bytes(vec![]))
+                    .with_header("Allow", &allowed)?),
+            (_, Some(allowed), _) =>
+                Ok(Response::new(Status::MethodNotAllowed, Content::text("Method not allowed"))
+                    .with_header("Allow", &allowed)?),
+            (_, None, Some(fallback)) => fallback.handle(request),
+            (_, None, None) => Ok(Response::new(Status::NotFound, Content::UnknownRoute)),
+        }
+    }
+
+    /// Returns the value for an `Allow` header listing the methods of the routes selected by
+    /// `filter`, plus those answered automatically (`HEAD` for `GET` routes, and `OPTIONS`);
+    /// `None` if no route is selected.
+    fn allowed_methods(&self, filter: impl Fn(&Route) -> bool) -> Option<String> {
+        let mut methods: Vec<&Method> = vec![];
+
+        for route in self.routes.iter().filter(|route| filter(route)) {
+            if !methods.contains(&&route.method) {
+                methods.push(&route.method);
+            }
+            if route.method == Method::Get && !methods.contains(&&Method::Head) {
+                methods.push(&Method::Head);
+            }
+        }
+        if methods.is_empty() {
+            return None;
+        }
+        if !methods.contains(&&Method::Options) {
+            methods.push(&Method::Options);
+        }
+
+        Some(methods.iter().map(|method| method.as_str()).collect::<Vec<_>>().join(", "))
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+        Router::handle(self, request)
+    }
+}
+
+impl fmt::Debug for Router {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.routes.iter().map(|route| format!("{} {}", route.method, route.pattern)))
+            .finish()
+    }
+}
+
+
+/// The segments of a request path, both raw and decoded.
+struct RequestPath<'p> {
+    raw: Vec<&'p str>,
+    decoded: Vec<String>,
+}
+
+impl<'p> RequestPath<'p> {
+
+    fn new(raw_path: &'p str) -> RequestPath<'p> {
+        let raw: Vec<&str> = raw_path.split('/').filter(|s| !s.is_empty()).collect();
+        // Paths that cannot be decoded are rejected when parsing the request.
+        let decoded = raw.iter()
+            .map(|segment| uri::decode_uri(segment).unwrap_or_default())
+            .collect();
+        RequestPath { raw, decoded }
+    }
+}
+
+
+impl Route {
+
+    /// If `path` matches this route, returns its captured parameters.
+    fn captures(&self, path: &RequestPath) -> Option<Vec<(String, String)>> {
+        use Segment::*;
+
+        let mut params = vec![];
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Literal(literal) => {
+                    if path.decoded.get(i) != Some(literal) {
+                        return None;
+                    }
+                },
+                Param(name) => params.push((name.clone(), path.decoded.get(i)?.clone())),
+                Wildcard(name) => {
+                    let tail = path.raw.get(i..).unwrap_or_default().join("/");
+                    params.push((name.clone(), uri::decode_uri(&tail).unwrap_or_default()));
+                    return Some(params);
+                },
+            }
+        }
+
+        (path.raw.len() == self.segments.len()).then_some(params)
+    }
+
+    /// Sorting key for the specificity of the route (lower is more specific).
+    fn rank(&self) -> Vec<u8> {
+        self.segments.iter()
+            .map(|segment| match segment {
+                Segment::Literal(_)  => 0,
+                Segment::Param(_)    => 1,
+                Segment::Wildcard(_) => 2,
+            })
+            .collect()
+    }
+
+    /// Returns `true` if both routes would match exactly the same requests.
+    fn conflicts_with(&self, other: &Route) -> bool {
+        use Segment::*;
+
+        self.method == other.method
+            && self.segments.len() == other.segments.len()
+            && self.segments.iter().zip(&other.segments).all(|pair| match pair {
+                (Literal(a), Literal(b)) => a == b,
+                (Param(_), Param(_)) | (Wildcard(_), Wildcard(_)) => true,
+                _ => false,
+            })
+    }
+}
+
+
+/// Parses a route pattern into its segments.
+fn parse_pattern(pattern: &str) -> Result<Vec<Segment>, RouteError> {
+
+    let invalid = |reason: &str| RouteError::InvalidPattern {
+        pattern: pattern.to_string(), reason: reason.to_string()
+    };
+
+    if !pattern.starts_with('/') {
+        return Err(invalid("Patterns must start with `/`"));
+    }
+
+    let mut segments = vec![];
+
+    for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+        if matches!(segments.last(), Some(Segment::Wildcard(_))) {
+            return Err(invalid("`*` parameters are only allowed in the last segment"));
+        }
+
+        let segment = if let Some(name) = segment.strip_prefix(':') {
+            Segment::Param(name.to_string())
+        } else if let Some(name) = segment.strip_prefix('*') {
+            Segment::Wildcard(name.to_string())
+        } else {
+            Segment::Literal(segment.to_string())
+        };
+
+        if let Segment::Param(name) | Segment::Wildcard(name) = &segment {
+            if name.is_empty() {
+                return Err(invalid("Missing parameter name"));
+            }
+            let repeated = segments.iter().any(|previous| matches!(previous,
+                Segment::Param(other) | Segment::Wildcard(other) if other == name
+            ));
+            if repeated {
+                return Err(invalid("Repeated parameter name"));
+            }
+        }
+        segments.push(segment);
+    }
+
+    Ok(segments)
+}
+
+
+/// Collects the routes for a `Router`. Errors in them are reported by `build()`.
+pub struct RouterBuilder {
+    routes: Vec<Route>,
+    fallback: Option<Box<dyn Handler>>,
+    error: Option<RouteError>,
+}
+
+impl RouterBuilder {
+
+    /// Adds a route for `GET` requests to paths matching `pattern`.
+    pub fn get<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    /// Adds a route for `HEAD` requests to paths matching `pattern`.
+    pub fn head<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Head, pattern, handler)
+    }
+
+    /// Adds a route for `POST` requests to paths matching `pattern`.
+    pub fn post<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    /// Adds a route for `PUT` requests to paths matching `pattern`.
+    pub fn put<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    /// Adds a route for `DELETE` requests to paths matching `pattern`.
+    pub fn delete<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Adds a route for `OPTIONS` requests to paths matching `pattern`, instead of the
+    /// automatic answer.
+    pub fn options<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Options, pattern, handler)
+    }
+
+    /// Adds a route for `PATCH` requests to paths matching `pattern`.
+    pub fn patch<F, R>(self, pattern: &str, handler: F) -> RouterBuilder
+    where
+        F: Fn(&Request) -> R + Send + Sync + 'static,
+        R: IntoResponse,
+    {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    /// Adds a route for `method` requests to paths matching `pattern`, handled by any
+    /// `Handler` (including closures, which then need their parameter type annotated).
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Handler) -> RouterBuilder {
+        match parse_pattern(pattern) {
+            Ok(segments) => self.routes.push(Route {
+                method, pattern: pattern.to_string(), segments, handler: Box::new(handler)
+            }),
+            Err(error) => {
+                self.error.get_or_insert(error);
+            },
+        }
+        self
+    }
+
+    /// Adds `handler` for `GET` (and so `HEAD`) requests to `prefix` and any path below it,
+    /// which is passed to it as the parameter `path` (e.g. `StaticFiles`).
+    pub fn mount(self, prefix: &str, handler: impl Handler) -> RouterBuilder {
+        let pattern = format!("{}/*path", prefix.trim_end_matches('/'));
+        self.route(Method::Get, &pattern, handler)
+    }
+
+    /// Sets the handler for requests that match no route.
+    pub fn fallback(mut self, handler: impl Handler) -> RouterBuilder {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Returns the router, or the first invalid pattern or conflict between routes found.
+    pub fn build(self) -> Result<Router, RouteError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+
+        for (i, route) in self.routes.iter().enumerate() {
+            if let Some(previous) = self.routes[..i].iter().find(|other| route.conflicts_with(other)) {
+                return Err(RouteError::Conflict {
+                    method: route.method.clone(),
+                    pattern: route.pattern.clone(),
+                    existing: previous.pattern.clone(),
+                });
+            }
+        }
+
+        Ok(Router { routes: self.routes, fallback: self.fallback })
+    }
+}
+
+
+/// Error building a `Router`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteError {
+    /// A pattern is not well formed.
+    InvalidPattern { pattern: String, reason: String },
+    /// A route would match the same requests as a previous one.
+    Conflict { method: Method, pattern: String, existing: String },
+}
+
+impl fmt::Display for RouteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RouteError::InvalidPattern { pattern, reason } =>
+                write!(f, "Invalid route pattern {pattern:?}: {reason}"),
+            RouteError::Conflict { method, pattern, existing } =>
+                write!(f, "Route {method} {pattern:?} conflicts with {existing:?}"),
+        }
+    }
+}
+
+impl Error for RouteError {}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Router, RouteError};
+    use crate::http::{Request, Response, req::Method, res::{Content, Status}};
+
+    /// Returns a handler that answers with `name` and the captured parameters.
+    fn echo(name: &'static str) -> impl Fn(&Request) -> Result<Response, Box<dyn std::error::Error>> {
+        move |request: &Request| {
+            let params: Vec<String> = request.params().iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect();
+            Ok(Response::new(Status::OK, Content::text(format!("{name} {}", params.join(" ")))))
+        }
+    }
+
+    fn route(router: &Router, method: &str, target: &str) -> (Status, String) {
+        let request = Request::parse(&format!("{method} {target} HTTP/1.1\r\n")).unwrap();
+        let response = router.handle(&request).unwrap();
+        let body = match response.content {
+            Content::Bytes(bytes) => String::from_utf8(bytes).unwrap(),
+            Content::UnknownRoute => "unknown".to_string(),
+            _ => panic!("Unexpected content"),
+        };
+        (response.status, body.trim().to_string())
+    }
+
+    #[test]
+    fn test_router_matching() {
+        let router = Router::builder()
+            .get("/", echo("root"))
+            .get("/users/:id", echo("user"))
+            .get("/users/me", echo("me"))
+            .put("/users/:id", echo("put_user"))
+            .get("/users/:id/posts/:post", echo("post"))
+            .get("/files/*path", echo("files"))
+            .get("/files/readme", echo("readme"))
+            .build()
+            .unwrap();
+
+        assert_eq!(route(&router, "GET", "/"), (Status::OK, "root".into()));
+        assert_eq!(route(&router, "GET", "/users/7"), (Status::OK, "user id=7".into()));
+        assert_eq!(route(&router, "GET", "/users/7/"), (Status::OK, "user id=7".into()));
+        assert_eq!(route(&router, "GET", "/users/me"), (Status::OK, "me".into()));
+        assert_eq!(route(&router, "PUT", "/users/me"), (Status::OK, "put_user id=me".into()));
+        assert_eq!(route(&router, "GET", "/users/a%20b?x=1"), (Status::OK, "user id=a b".into()));
+        assert_eq!(route(&router, "GET", "/users/7/posts/3"), (Status::OK, "post id=7 post=3".into()));
+        assert_eq!(route(&router, "GET", "/files/a/b%2Fc.txt"), (Status::OK, "files path=a/b/c.txt".into()));
+        assert_eq!(route(&router, "GET", "/files/readme"), (Status::OK, "readme".into()));
+        assert_eq!(route(&router, "GET", "/files"), (Status::OK, "files path=".into()));
+        assert_eq!(route(&router, "HEAD", "/users/7"), (Status::OK, "user id=7".into()));
+
+        assert_eq!(route(&router, "GET", "/users"), (Status::NotFound, "unknown".into()));
+        assert_eq!(route(&router, "GET", "/users/7/posts"), (Status::NotFound, "unknown".into()));
+
+        let router = Router::builder()
+            .get("/", echo("root"))
+            .fallback(echo("fallback"))
+            .build()
+            .unwrap();
+        assert_eq!(route(&router, "GET", "/other"), (Status::OK, "fallback".into()));
+    }
+
+    #[test]
+    fn test_router_allowed_methods() {
+        let router = Router::builder()
+            .get("/users/:id", echo("user"))
+            .put("/users/:id", echo("put_user"))
+            .delete("/users/me", echo("delete_me"))
+            .get("/about", echo("about"))
+            .options("/about", echo("options_about"))
+            .build()
+            .unwrap();
+
+        let allow = |method: &str, target: &str| {
+            let request = Request::parse(&format!("{method} {target} HTTP/1.1\r\n")).unwrap();
+            let response = router.handle(&request).unwrap();
+            (response.status, response.headers.get("Allow").map(str::to_string))
+        };
+
+        assert_eq!(allow("POST", "/users/7"), (Status::MethodNotAllowed, Some("GET, HEAD, PUT, OPTIONS".into())));
+        assert_eq!(allow("POST", "/users/me"), (Status::MethodNotAllowed, Some("GET, HEAD, PUT, DELETE, OPTIONS".into())));
+        assert_eq!(allow("OPTIONS", "/users/7"), (Status::NoContent, Some("GET, HEAD, PUT, OPTIONS".into())));
+        assert_eq!(allow("OPTIONS", "*"), (Status::NoContent, Some("GET, HEAD, PUT, DELETE, OPTIONS".into())));
+        assert_eq!(allow("OPTIONS", "/about"), (Status::OK, None));
+        assert_eq!(allow("POST", "/about"), (Status::MethodNotAllowed, Some("GET, HEAD, OPTIONS".into())));
+        assert_eq!(allow("OPTIONS", "/other"), (Status::NotFound, None));
+        assert_eq!(allow("POST", "/other"), (Status::NotFound, None));
+    }
+
+    #[test]
+    fn test_router_errors() {
+        let conflict = Router::builder()
+            .get("/users/:id", echo("a"))
+            .put("/users/:id", echo("b"))
+            .get("/users/:name/", echo("c"))
+            .build();
+        assert_eq!(conflict.unwrap_err(), RouteError::Conflict {
+            method: Method::Get, pattern: "/users/:name/".into(), existing: "/users/:id".into()
+        });
+
+        assert!(Router::builder().get("/a/:id", echo("a")).get("/a/b", echo("b")).build().is_ok());
+        assert!(Router::builder().get("/a/*x", echo("a")).get("/a/*y", echo("b")).build().is_err());
+
+        for pattern in ["users", "/a/*rest/b", "/a/:", "/a/*", "/:id/:id"] {
+            let result = Router::builder().get(pattern, echo("a")).build();
+            assert!(matches!(result, Err(RouteError::InvalidPattern { .. })), "{pattern}");
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Application state shared by all requests, registered by type.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+
+
+/// Values of any type, at most one of each, accessible from any thread.
+#[derive(Default)]
+pub(crate) struct State {
+    values: HashMap<TypeId, Box<dyn Any + Send + Sync>>,
+}
+
+impl State {
+
+    /// Adds `value`, replacing any previous value of the same type.
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) {
+        self.values.insert(TypeId::of::<T>(), Box::new(value));
+    }
+
+    /// Returns the value of type `T`, if any.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values.get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+}
+
+impl fmt::Debug for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "State({} values)", self.values.len())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::State;
+
+    #[test]
+    fn test_state() {
+        struct Counter(usize);
+
+        let mut state = State::default();
+        state.insert(Counter(1));
+        state.insert("name");
+        assert_eq!(state.get::<Counter>().map(|counter| counter.0), Some(1));
+        assert_eq!(state.get::<&str>(), Some(&"name"));
+        assert!(state.get::<String>().is_none());
+
+        state.insert(Counter(2));
+        assert_eq!(state.get::<Counter>().map(|counter| counter.0), Some(2));
+    }
+}
@@ -0,0 +1,313 @@
+//! Handler serving the files of a directory.
+//!
+//! Example, serving `./public` at `/static/` within a router:
+//! ```no_run
+//! # fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use shttp::{Router, StaticFiles};
+//!
+//! let router = Router::builder()
+//!     .mount("/static", StaticFiles::new("public")?.with_listing(true))
+//!     .build()?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::cmp::Ordering;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::Error as ServerError;
+use crate::handler::Handler;
+use crate::http::{Request, Response, date::DateTime, res::{Content, Status}};
+use crate::uri;
+
+
+/// Name of the file served for directories.
+const INDEX_FILE: &str = "index.html";
+
+
+/// Serves the files under a root directory. Mounted with `RouterBuilder::mount()`, the path
+/// below the mount point is taken from the `path` parameter; otherwise the whole request path
+/// is used.
+///
+/// Files are streamed with their `Content-Type` (see `MimeTypes`). Directories are answered with
+/// their `index.html` file when present, or else with a listing of their contents if enabled.
+///
+/// Files are sent as `Content::UserFile`, so precompressed siblings (`file.br` or `file.gz`)
+/// are served as files of their own but never in place of the original, unlike for server
+/// files (`Content::ServerFile`).
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    listing: bool,
+}
+
+impl StaticFiles {
+
+    /// Creates a handler for the files under `root`, which must be an existing directory.
+    pub fn new(root: impl AsRef<Path>) -> io::Result<StaticFiles> {
+        let root = root.as_ref().canonicalize()?;
+        if !root.is_dir() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "Not a directory"));
+        }
+        Ok(StaticFiles { root, listing: false })
+    }
+
+    /// Enables or disables listing the contents of directories without an index file.
+    pub fn with_listing(mut self, listing: bool) -> StaticFiles {
+        self.listing = listing;
+        self
+    }
+
+    /// Resolves `rel_path` (a decoded, `/`-separated path) to an existing file or directory
+    /// under the root.
+    ///
+    /// This is the only place where request paths become filesystem paths: `.` and `..`
+    /// segments are rejected, and so is anything that resolves (through symbolic links) to a
+    /// location outside the root.
+    fn resolve(&self, rel_path: &str) -> Result<PathBuf, ServerError> {
+        let not_found = || ServerError::status(Status::NotFound, "Not found");
+
+        let mut path = self.root.clone();
+        for segment in rel_path.split('/').filter(|s| !s.is_empty()) {
+            // Backslashes and drive prefixes would be separators or roots on some platforms.
+            if segment == "." || segment == ".." || segment.contains(['\\', '\0', ':']) {
+                return Err(ServerError::status(Status::BadRequest, "Invalid path"));
+            }
+            path.push(segment);
+        }
+
+        let path = path.canonicalize().map_err(|_| not_found())?;
+        if !path.starts_with(&self.root) {
+            return Err(not_found());
+        }
+        Ok(path)
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &Request) -> Result<Response, Box<dyn Error>> {
+
+        let rel_path = request.param("path").unwrap_or_else(|| request.path.clone());
+        let path = self.resolve(&rel_path)?;
+
+        if !path.is_dir() {
+            // The server gives it its `Content-Type` when resolving the response.
+            return Ok(Response::new(Status::OK, Content::UserFile(path)));
+        }
+
+        // Relative links in the directory page need the URL to end with `/`.
+        let (raw_path, raw_query) = uri::split_target(&request.target);
+        if !raw_path.ends_with('/') {
+            let location = match raw_query {
+                Some(query) => format!("{raw_path}/?{query}"),
+                None => format!("{raw_path}/"),
+            };
+            return Ok(Response::new(Status::MovedPermanently, Content::text("Moved"))
+                .with_header("Location", &location)?);
+        }
+
+        let index = path.join(INDEX_FILE);
+        if index.is_file() {
+            let index = self.resolve(&format!("{rel_path}/{INDEX_FILE}"))?;
+            return Ok(Response::new(Status::OK, Content::UserFile(index)));
+        }
+
+        if !self.listing {
+            return Err(ServerError::status(Status::NotFound, "Not found").into());
+        }
+
+        let sort = SortOrder::from_query(request.query.get("sort"), request.query.get("order"));
+        let html = render_listing(&path, &rel_path, sort)?;
+        Ok(Response::new(Status::OK, Content::text(html))
+            .with_header("Content-Type", "text/html; charset=utf-8")?)
+    }
+}
+
+
+/// An entry of a directory listing.
+struct Entry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<DateTime>,
+}
+
+
+/// Order of a directory listing, as given in the query (`?sort=size&order=desc`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SortOrder {
+    key: SortKey,
+    descending: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortKey {
+    Name,
+    Size,
+    Modified,
+}
+
+impl SortOrder {
+
+    fn from_query(sort: Option<&str>, order: Option<&str>) -> SortOrder {
+        let key = match sort {
+            Some("size")        => SortKey::Size,
+            Some("modified")    => SortKey::Modified,
+            _                   => SortKey::Name,
+        };
+        SortOrder { key, descending: order == Some("desc") }
+    }
+
+    /// Compares entries, always with directories first.
+    fn compare(&self, a: &Entry, b: &Entry) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Name       => a.name.cmp(&b.name),
+            SortKey::Size       => a.size.cmp(&b.size).then_with(|| a.name.cmp(&b.name)),
+            SortKey::Modified   => a.modified.cmp(&b.modified).then_with(|| a.name.cmp(&b.name)),
+        };
+        let ordering = if self.descending { ordering.reverse() } else { ordering };
+        b.is_dir.cmp(&a.is_dir).then(ordering)
+    }
+
+    /// Returns the query for a column header link: sorts by `key`, reversing the order if
+    /// already sorted by it.
+    fn link_query(&self, key: SortKey) -> String {
+        let name = match key {
+            SortKey::Name       => "name",
+            SortKey::Size       => "size",
+            SortKey::Modified   => "modified",
+        };
+        let order = if self.key == key && !self.descending { "desc" } else { "asc" };
+        format!("?sort={name}&order={order}")
+    }
+}
+
+
+/// Returns an HTML page listing the contents of the directory `path`, shown as `rel_path`.
+fn render_listing(path: &Path, rel_path: &str, sort: SortOrder) -> io::Result<String> {
+
+    let mut entries = vec![];
+    for dir_entry in fs::read_dir(path)? {
+        let dir_entry = dir_entry?;
+        let Ok(metadata) = fs::metadata(dir_entry.path()) else {
+            continue; // Broken symbolic links
+        };
+        entries.push(Entry {
+            name: dir_entry.file_name().to_string_lossy().to_string(),
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified().ok().map(DateTime::from_system_time),
+        });
+    }
+    entries.sort_by(|a, b| sort.compare(a, b));
+
+    let title = escape_html(&format!("/{}", rel_path.trim_matches('/')));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Index of {title}</title></head>\n\
+        <body>\n<h1>Index of {title}</h1>\n<table>\n<tr>\
+        <th><a href=\"{}\">Name</a></th><th><a href=\"{}\">Size</a></th>\
+        <th><a href=\"{}\">Modified (UTC)</a></th></tr>\n\
+        <tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n",
+        escape_html(&sort.link_query(SortKey::Name)), escape_html(&sort.link_query(SortKey::Size)),
+        escape_html(&sort.link_query(SortKey::Modified)),
+    );
+
+    for entry in &entries {
+        let suffix = if entry.is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "<tr><td><a href=\"{}{suffix}\">{}{suffix}</a></td><td>{}</td><td>{}</td></tr>\n",
+            uri::encode_path_segment(&entry.name),
+            escape_html(&entry.name),
+            if entry.is_dir { String::new() } else { entry.size.to_string() },
+            entry.modified.map(|date| date.to_string()).unwrap_or_default(),
+        ));
+    }
+    html.push_str("</table>\n</body>\n</html>\n");
+
+    Ok(html)
+}
+
+
+/// Escapes text to be included in HTML content or attribute values.
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::StaticFiles;
+    use crate::handler::Handler;
+    use crate::http::{Request, res::{Content, Status}};
+    use crate::test_dir::TestDir;
+
+    fn get(files: &StaticFiles, target: &str) -> (Status, Option<String>, Content) {
+        let request = Request::parse(&format!("GET {target} HTTP/1.1\r\n")).unwrap();
+        match files.handle(&request) {
+            Ok(response) => (
+                response.status,
+                response.headers.get("Content-Type").or(response.headers.get("Location")).map(String::from),
+                response.content,
+            ),
+            Err(error) => match error.downcast::<crate::Error>() {
+                Ok(error) => (error.to_status(), None, Content::UnknownRoute),
+                Err(error) => panic!("Unexpected error: {error}"),
+            },
+        }
+    }
+
+    #[test]
+    fn test_static_files() {
+        let dir = TestDir::new("static");
+        let root = dir.join("root");
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::create_dir_all(root.join("site")).unwrap();
+        fs::write(root.join("style.css"), "body {}").unwrap();
+        fs::write(root.join("site/index.html"), "<p>Home</p>").unwrap();
+        fs::write(root.join("docs/b <&>.txt"), "bb").unwrap();
+        fs::write(root.join("docs/a.txt"), "aaa").unwrap();
+        fs::write(dir.join("secret.txt"), "secret").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(dir.join("secret.txt"), root.join("escape.txt")).unwrap();
+
+        let files = StaticFiles::new(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let (status, _, content) = get(&files, "/style.css");
+        assert_eq!(status, Status::OK);
+        assert!(matches!(content, Content::UserFile(path) if path == root.join("style.css")));
+
+        let (status, _, content) = get(&files, "/site/");
+        assert_eq!(status, Status::OK);
+        assert!(matches!(content, Content::UserFile(path) if path == root.join("site/index.html")));
+
+        let (status, location, _) = get(&files, "/site?x=1");
+        assert_eq!((status, location.as_deref()), (Status::MovedPermanently, Some("/site/?x=1")));
+
+        assert_eq!(get(&files, "/docs/").0, Status::NotFound);
+        assert_eq!(get(&files, "/missing.txt").0, Status::NotFound);
+        assert_eq!(get(&files, "/docs/../../secret.txt").0, Status::BadRequest);
+        assert_eq!(get(&files, "/docs/%2e%2e/%2E%2E/secret.txt").0, Status::BadRequest);
+        #[cfg(unix)]
+        assert_eq!(get(&files, "/escape.txt").0, Status::NotFound);
+
+        let files = files.with_listing(true);
+        let (status, _, content) = get(&files, "/docs/?sort=size&order=desc");
+        assert_eq!(status, Status::OK);
+        let Content::Bytes(html) = content else { panic!("Listing expected") };
+        let html = String::from_utf8(html).unwrap();
+        let a = html.find("href=\"a.txt\"").unwrap();
+        let b = html.find("href=\"b%20%3C%26%3E.txt\">b &lt;&amp;&gt;.txt<").unwrap();
+        assert!(a < b);
+        assert!(html.contains("href=\"?sort=size&amp;order=asc\""));
+    }
+}
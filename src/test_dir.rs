@@ -0,0 +1,37 @@
+//! Temporary directories for tests working with files.
+
+use std::fs;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+
+/// A fresh directory under the system temporary directory, removed when dropped (also when
+/// the test fails).
+pub struct TestDir {
+    path: PathBuf,
+}
+
+impl TestDir {
+
+    /// Creates the directory for the test `name`, replacing any left by an earlier run.
+    pub fn new(name: &str) -> TestDir {
+        let path = std::env::temp_dir().join(format!("shttp-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDir { path }
+    }
+}
+
+impl Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
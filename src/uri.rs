@@ -1,6 +1,6 @@
-/// Utility functions to decode URIs.
+//! Utility functions to decode (and encode) URIs.
 
-const PERCENT_CODE: u8 = '%' as u8; // ASCII code for '%'
+const PERCENT_CODE: u8 = b'%'; // ASCII code for '%'
 
 
 /// Decodes a percent-encoded URI into its corresponding UTF-8 string.
@@ -30,6 +30,41 @@ pub fn decode_uri(encoded_uri: &str) -> Result<String, std::string::FromUtf8Erro
 }
 
 
+/// Decodes a component of an `application/x-www-form-urlencoded` string (like a key or a
+/// value in a URI query), where `+` stands for a space.
+pub fn decode_form_component(encoded: &str) -> Result<String, std::string::FromUtf8Error> {
+    decode_uri(&encoded.replace('+', " "))
+}
+
+
+/// Splits a raw request target into its path and query (without the `?`). Any fragment
+/// (not expected from clients) is discarded.
+pub fn split_target(target: &str) -> (&str, Option<&str>) {
+    let target = target.split('#').next().unwrap_or_default();
+
+    match target.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (target, None),
+    }
+}
+
+
+/// Percent-encodes `segment` to be used as a single path segment: any byte other than ASCII
+/// letters, digits and `-._~` is encoded (including `/`).
+pub fn encode_path_segment(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+
+    for byte in segment.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
+}
+
+
 /// If the first two characters of `string` are hex digits, return their numerical value
 /// and the rest of the string; otherwise, return the char code for '%' and the full original
 /// string.
@@ -53,7 +88,7 @@ fn shift_encoded_hex(string: &str) -> (u8, &str) {
 
 #[cfg(test)]
 mod tests {
-    use crate::uri::decode_uri;
+    use crate::uri::{decode_uri, decode_form_component, split_target, encode_path_segment};
     
     macro_rules! check_decode {
         ($encoded:literal, $decoded:literal) => {
@@ -89,5 +124,27 @@ mod tests {
         check_decode!("Price: %E2%82%AC79", "Price: €79");
         check_decode!("Currencies:%20$%E2%82%AC%C2%A3", "Currencies: $€£");
     }
-}
 
+    #[test]
+    fn test_decode_form_component() {
+        assert_eq!(decode_form_component("two+words"),  Ok("two words".into()));
+        assert_eq!(decode_form_component("1%2B1"),      Ok("1+1".into()));
+        assert_eq!(decode_form_component("a%26b%3Dc"),  Ok("a&b=c".into()));
+    }
+
+    #[test]
+    fn test_encode_path_segment() {
+        assert_eq!(encode_path_segment("file-1.txt"),   "file-1.txt");
+        assert_eq!(encode_path_segment("a b/c?d#e%"),   "a%20b%2Fc%3Fd%23e%25");
+        assert_eq!(encode_path_segment("€"),            "%E2%82%AC");
+        assert_eq!(decode_uri(&encode_path_segment("a b/€")), Ok("a b/€".into()));
+    }
+
+    #[test]
+    fn test_split_target() {
+        assert_eq!(split_target("/info"),           ("/info", None));
+        assert_eq!(split_target("/info?"),          ("/info", Some("")));
+        assert_eq!(split_target("/a?b=1&c=2#d"),    ("/a", Some("b=1&c=2")));
+        assert_eq!(split_target("/a?b=?"),          ("/a", Some("b=?")));
+    }
+}